@@ -3,7 +3,7 @@ use std::ptr::NonNull;
 use crate::{
 	link::{self, Link, Node as _},
 	util::alloc,
-	version::PartialVersion,
+	version::{PartialVersion, Version},
 };
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -55,54 +55,511 @@ unsafe impl<T: Clone> link::Node<Tag> for Node<T> {
 	}
 }
 
+impl<T> Node<T> {
+	pub fn new(value: T) -> Node<T> {
+		Node {
+			link_container: core::array::from_fn(|_| None),
+			value,
+			copy: None,
+		}
+	}
+}
+
 impl<T: Ord + Clone> Node<T> {
-	pub fn insert(&mut self, value: T, version: PartialVersion) {
-		if value < self.value {
-			match self.get(Tag::LeftChild, version) {
-				Some(mut left) => unsafe { left.as_mut() }.insert(value, version),
+	/// Inserts the value into the tree in the given version, which must be the newest version of
+	/// the tree. This node must be the root of the tree in the version. Returns the root of the
+	/// tree in the version, which is a copy of this node if this node ran out of links.
+	pub fn insert(&mut self, value: T, version: PartialVersion) -> NonNull<Node<T>> {
+		let root = NonNull::from(self);
+		let mut current = root;
+		loop {
+			let node = unsafe { current.as_ref() };
+			let tag = if value < node.value {
+				Tag::LeftChild
+			} else {
+				Tag::RightChild
+			};
+			match node.get(tag, version) {
+				Some(next) => current = next,
 				None => {
-					self.add(
-						Tag::LeftChild,
-						alloc(Node {
-							link_container: core::array::from_fn(|_| None),
-							value,
-							copy: None,
-						}),
-						version,
-						false,
-					);
+					link::Node::add(current, tag, Some(alloc(Node::new(value))), version);
+					return link::Node::current(root);
 				}
 			}
-		} else {
-			match self.get(Tag::RightChild, version) {
-				Some(mut right) => unsafe { right.as_mut() }.insert(value, version),
-				None => {
-					self.add(
-						Tag::RightChild,
-						alloc(Node {
-							link_container: core::array::from_fn(|_| None),
-							value,
-							copy: None,
-						}),
-						version,
-						false,
-					);
+		}
+	}
+
+	pub fn contains(&self, value: &T, version: PartialVersion) -> bool {
+		let child = match value.cmp(&self.value) {
+			std::cmp::Ordering::Less => Tag::LeftChild,
+			std::cmp::Ordering::Equal => return true,
+			std::cmp::Ordering::Greater => Tag::RightChild,
+		};
+		self.get(child, version)
+			.map(|child| unsafe { child.as_ref() }.contains(value, version))
+			.unwrap_or(false)
+	}
+
+	/// Removes one occurrence of the value from the tree in the given version, which must be the
+	/// newest version of the tree. This node must be the root of the tree in the version. The
+	/// value stays in the tree in earlier versions.
+	///
+	/// Returns None if the value is not in the tree. Otherwise returns the root of the tree in the
+	/// version, which is None if the tree is empty. The root is not this node if the value of this
+	/// node was removed or this node was copied.
+	///
+	/// A node with two children is replaced by its in order successor, which is unlinked from its
+	/// parent and linked to the children of the removed node.
+	pub fn remove(
+		&mut self,
+		value: &T,
+		version: PartialVersion,
+	) -> Option<Option<NonNull<Node<T>>>> {
+		let root = NonNull::from(self);
+		// Linking nodes may copy them, so the newest copy of a node is read
+		let get = |node: NonNull<Self>, tag| {
+			unsafe { link::Node::current(node).as_ref() }.get(tag, version)
+		};
+		let set = |node, tag, child| link::Node::add(node, tag, child, version);
+
+		// The link from the parent of the removed node to the removed node
+		let mut parent = None;
+		let mut removed = root;
+		loop {
+			let tag = match value.cmp(&unsafe { removed.as_ref() }.value) {
+				std::cmp::Ordering::Less => Tag::LeftChild,
+				std::cmp::Ordering::Equal => break,
+				std::cmp::Ordering::Greater => Tag::RightChild,
+			};
+			parent = Some((removed, tag));
+			removed = get(removed, tag)?;
+		}
+
+		let replacement = match (get(removed, Tag::LeftChild), get(removed, Tag::RightChild)) {
+			(Some(left), Some(right)) => {
+				let mut successor = right;
+				while let Some(next) = get(successor, Tag::LeftChild) {
+					successor = next;
 				}
+				// The children of the removed node are unlinked before they are linked to the
+				// successor, and the successor is unlinked from its parent before it is linked to
+				// the parent of the removed node
+				set(removed, Tag::RightChild, None);
+				set(removed, Tag::LeftChild, None);
+				if successor != right {
+					let successor_parent =
+						get(successor, Tag::LeftParent).expect("the successor to be a left child");
+					let successor_right = get(successor, Tag::RightChild);
+					set(successor, Tag::RightChild, None);
+					set(successor_parent, Tag::LeftChild, successor_right);
+					set(successor, Tag::RightChild, Some(right));
+				}
+				set(successor, Tag::LeftChild, Some(left));
+				Some(successor)
+			}
+			(Some(child), None) => {
+				set(removed, Tag::LeftChild, None);
+				Some(child)
+			}
+			(None, Some(child)) => {
+				set(removed, Tag::RightChild, None);
+				Some(child)
 			}
+			(None, None) => None,
+		};
+		match parent {
+			Some((parent, tag)) => {
+				set(parent, tag, replacement);
+				Some(Some(link::Node::current(root)))
+			}
+			None => Some(replacement.map(link::Node::current)),
 		}
 	}
 
-	pub fn contains(&self, value: &T, version: PartialVersion) -> bool {
-		match value.cmp(&self.value) {
-			std::cmp::Ordering::Less => self
+	/// Returns an in order iterator over the values in the given version, which are the values
+	/// inserted at versions less than or equal to the given version.
+	pub fn in_order(&self, version: PartialVersion) -> impl Iterator<Item = &T> {
+		let mut stack = Vec::new();
+		let mut current = Some(self);
+		while let Some(node) = current {
+			stack.push(node);
+			current = node
 				.get(Tag::LeftChild, version)
-				.map(|left| unsafe { left.as_ref() }.contains(value, version))
-				.unwrap_or(false),
-			std::cmp::Ordering::Equal => true,
-			std::cmp::Ordering::Greater => self
-				.get(Tag::RightChild, version)
-				.map(|right| unsafe { right.as_ref() }.contains(value, version))
-				.unwrap_or(false),
+				.map(|next| unsafe { next.as_ref() });
+		}
+		Iter { stack, version }
+	}
+
+	/// Returns the smallest value in the given version by following the left children. A tree
+	/// always has a value, as this node is its root.
+	pub fn min(&self, version: PartialVersion) -> &T {
+		&self.extreme(Tag::LeftChild, version).value
+	}
+
+	/// Returns the largest value in the given version by following the right children.
+	pub fn max(&self, version: PartialVersion) -> &T {
+		&self.extreme(Tag::RightChild, version).value
+	}
+
+	/// Returns the last node reached by following the children with the tag in the version.
+	fn extreme(&self, tag: Tag, version: PartialVersion) -> &Node<T> {
+		let mut node = self;
+		while let Some(next) = node.get(tag, version) {
+			node = unsafe { next.as_ref() };
+		}
+		node
+	}
+
+	/// Returns the number of nodes on the longest path from this node to a leaf in the given
+	/// version.
+	pub fn height(&self, version: PartialVersion) -> usize {
+		let height = |tag| {
+			self.get(tag, version)
+				.map(|child| unsafe { child.as_ref() }.height(version))
+				.unwrap_or(0)
+		};
+		height(Tag::LeftChild).max(height(Tag::RightChild)) + 1
+	}
+
+	/// Returns an in order iterator over the values greater than or equal to start in the given
+	/// version. Only the path to start is visited to find the first value, so the values less
+	/// than start are skipped without being visited.
+	pub fn iter_from<'a>(
+		&'a self,
+		start: &T,
+		version: PartialVersion,
+	) -> impl Iterator<Item = &'a T> {
+		let mut stack = Vec::new();
+		let mut current = Some(self);
+		while let Some(node) = current {
+			let next = if node.value >= *start {
+				stack.push(node);
+				node.get(Tag::LeftChild, version)
+			} else {
+				node.get(Tag::RightChild, version)
+			};
+			current = next.map(|next| unsafe { next.as_ref() });
+		}
+		Iter { stack, version }
+	}
+}
+
+/// In order iterator over a version of a tree. The stack holds the nodes whose value and right
+/// subtree have not been visited yet, with the next node on top.
+struct Iter<'a, T> {
+	stack: Vec<&'a Node<T>>,
+	version: PartialVersion,
+}
+
+impl<'a, T: Clone> Iterator for Iter<'a, T> {
+	type Item = &'a T;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let node = self.stack.pop()?;
+		let mut current = node.get(Tag::RightChild, self.version);
+		while let Some(next) = current {
+			let next = unsafe { next.as_ref() };
+			self.stack.push(next);
+			current = next.get(Tag::LeftChild, self.version);
+		}
+		Some(&node.value)
+	}
+}
+
+/// Persistent binary search tree. Every insertion creates a new version of the tree, and the
+/// handle of every version reads the values of that version.
+///
+/// The tree is partially persistent, as the nodes are made persistent by node copying, so only
+/// the newest version can be modified. Inserting into an older version returns None.
+pub struct PersistentBst<T> {
+	// The root of the tree in this version
+	root: Option<NonNull<Node<T>>>,
+	version: Version,
+}
+
+impl<T: Ord + Clone> Default for PersistentBst<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Ord + Clone> PersistentBst<T> {
+	/// Creates a new empty tree with a new version tree.
+	pub fn new() -> PersistentBst<T> {
+		PersistentBst {
+			root: None,
+			version: Version::new(),
 		}
 	}
+
+	/// Inserts the value in a new version and returns the new version. Returns None if this is
+	/// not the newest version of the tree, that is if a value has been inserted into this version
+	/// already.
+	pub fn insert(&self, value: T) -> Option<PersistentBst<T>> {
+		if !self.version.is_leaf() {
+			return None;
+		}
+		let version = self.version.insert_after();
+		let root = match self.root {
+			Some(mut root) => unsafe { root.as_mut() }.insert(value, version.primary),
+			None => alloc(Node::new(value)),
+		};
+		Some(PersistentBst {
+			root: Some(root),
+			version,
+		})
+	}
+
+	/// Returns true if the value is in this version of the tree.
+	pub fn contains(&self, value: &T) -> bool {
+		self.root
+			.is_some_and(|root| unsafe { root.as_ref() }.contains(value, self.version.primary))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::ptr::NonNull;
+
+	use crate::{util::alloc, version::PartialVersion};
+
+	use super::{Node, PersistentBst};
+
+	#[test]
+	fn iter_from() {
+		let version = PartialVersion::new();
+		let mut root = Node::new(50);
+		let mut values = vec![50];
+		for _ in 0..100 {
+			let value = fastrand::u64(..100);
+			root.insert(value, version);
+			values.push(value);
+		}
+		values.sort();
+		for start in [0, 25, 50, 75, 100] {
+			let expected: Vec<_> = values.iter().filter(|&&value| value >= start).collect();
+			assert_eq!(
+				root.iter_from(&start, version).collect::<Vec<_>>(),
+				expected
+			);
+		}
+	}
+
+	#[test]
+	fn in_order() {
+		let first = PartialVersion::new();
+		let second = first.insert_after();
+		let mut root = Node::new(50);
+		let mut values = vec![50];
+		for _ in 0..100 {
+			let value = fastrand::u64(..100);
+			root.insert(value, first);
+			values.push(value);
+		}
+		let mut later = values.clone();
+		for _ in 0..100 {
+			let value = fastrand::u64(..100);
+			root.insert(value, second);
+			later.push(value);
+		}
+		values.sort();
+		later.sort();
+		assert!(root.in_order(first).eq(&values));
+		assert!(root.in_order(second).eq(&later));
+	}
+
+	/// Removes the value from the tree with the root in the version and returns the root of the
+	/// tree in the version. Panics if the value is not in the tree or the tree becomes empty.
+	fn remove<T: Ord + Clone>(
+		mut root: NonNull<Node<T>>,
+		value: T,
+		version: PartialVersion,
+	) -> NonNull<Node<T>> {
+		unsafe { root.as_mut() }
+			.remove(&value, version)
+			.expect("the value to be in the tree")
+			.expect("the tree to be non-empty")
+	}
+
+	fn node<'a, T>(node: NonNull<Node<T>>) -> &'a Node<T> {
+		unsafe { node.as_ref() }
+	}
+
+	#[test]
+	fn remove_cases() {
+		let base = PartialVersion::new();
+		let mut root = alloc(Node::new(50));
+		// 10 is a leaf, 30 has a single child and 70 has two children, whose successor 75 is not
+		// its right child
+		for value in [30, 70, 20, 60, 80, 10, 75] {
+			unsafe { root.as_mut() }.insert(value, base);
+		}
+		let leaf = base.insert_after();
+		let leaf_root = remove(root, 10, leaf);
+		let single = leaf.insert_after();
+		let single_root = remove(leaf_root, 30, single);
+		let two = single.insert_after();
+		let mut two_root = remove(single_root, 70, two);
+		assert!(unsafe { two_root.as_mut() }.remove(&70, two).is_none());
+		assert!(unsafe { two_root.as_mut() }.remove(&40, two).is_none());
+
+		assert!(node(root)
+			.in_order(base)
+			.eq(&[10, 20, 30, 50, 60, 70, 75, 80]));
+		assert!(node(leaf_root)
+			.in_order(leaf)
+			.eq(&[20, 30, 50, 60, 70, 75, 80]));
+		assert!(node(single_root)
+			.in_order(single)
+			.eq(&[20, 50, 60, 70, 75, 80]));
+		assert!(node(two_root).in_order(two).eq(&[20, 50, 60, 75, 80]));
+		assert!(node(two_root).iter_from(&60, two).eq(&[60, 75, 80]));
+		for (value, version, old_root) in [
+			(10, leaf, root),
+			(30, single, leaf_root),
+			(70, two, single_root),
+		] {
+			assert!(node(root).contains(&value, base));
+			assert!(node(old_root).contains(&value, version.predecessor().unwrap()));
+			assert!(!node(two_root).contains(&value, version));
+		}
+		// The nodes are unlinked, so the tree shrinks
+		assert_eq!(node(root).height(base), 4);
+		assert_eq!(node(two_root).height(two), 3);
+
+		// The successor of the root is its right child in the next removal
+		let root_removed = two.insert_after();
+		let new_root = remove(two_root, 50, root_removed);
+		assert_eq!(node(new_root).value, 60);
+		assert!(node(new_root).in_order(root_removed).eq(&[20, 60, 75, 80]));
+		assert!(node(two_root).in_order(two).eq(&[20, 50, 60, 75, 80]));
+		let right_child = root_removed.insert_after();
+		let mut new_root = remove(new_root, 60, right_child);
+		assert_eq!(node(new_root).value, 75);
+		assert!(node(new_root).in_order(right_child).eq(&[20, 75, 80]));
+
+		// Removed values can be inserted again
+		let reinserted = right_child.insert_after();
+		let new_root = unsafe { new_root.as_mut() }.insert(50, reinserted);
+		assert!(node(new_root).contains(&50, reinserted));
+		assert!(!node(new_root).contains(&50, right_child));
+		assert!(node(root)
+			.in_order(base)
+			.eq(&[10, 20, 30, 50, 60, 70, 75, 80]));
+	}
+
+	#[test]
+	#[should_panic = "links can only be modified in the newest version"]
+	fn modify_old_version() {
+		let old = PartialVersion::new();
+		let new = old.insert_after();
+		let mut root = Node::new(50);
+		root.insert(30, new);
+		root.insert(70, old);
+	}
+
+	#[test]
+	fn remove_random() {
+		let mut version = PartialVersion::new();
+		let mut root = Some(alloc(Node::new(50)));
+		let mut snapshots = vec![(version, root, vec![50])];
+		let mut model = vec![50];
+		// Few distinct values relink the same nodes many times, so nodes run out of links and are
+		// copied
+		for _ in 0..2000 {
+			version = version.insert_after();
+			let value = fastrand::u64(..40);
+			if fastrand::bool() {
+				let removed = model
+					.iter()
+					.position(|&v| v == value)
+					.map(|i| model.remove(i));
+				let new_root =
+					root.and_then(|mut root| unsafe { root.as_mut() }.remove(&value, version));
+				assert_eq!(new_root.is_some(), removed.is_some());
+				if let Some(new_root) = new_root {
+					root = new_root;
+				}
+			} else {
+				root = Some(match root {
+					Some(mut root) => unsafe { root.as_mut() }.insert(value, version),
+					None => alloc(Node::new(value)),
+				});
+				model.push(value);
+			}
+			model.sort();
+			snapshots.push((version, root, model.clone()));
+		}
+		for (version, root, model) in snapshots {
+			let Some(root) = root else {
+				assert!(model.is_empty());
+				continue;
+			};
+			let root = unsafe { root.as_ref() };
+			assert!(root.in_order(version).eq(&model));
+			for value in 0..40 {
+				assert_eq!(root.contains(&value, version), model.contains(&value));
+			}
+		}
+	}
+
+	#[test]
+	fn min_max_height() {
+		let base = PartialVersion::new();
+		let mut root = alloc(Node::new(50));
+		let mut insert = |value, version| unsafe { root.as_mut() }.insert(value, version);
+		for value in [30, 70, 20, 40, 60] {
+			insert(value, base);
+		}
+		// 10 and 90 extend the height of the left and right subtree in later versions
+		let left = base.insert_after();
+		insert(10, left);
+		let right = left.insert_after();
+		insert(80, right);
+		insert(90, right);
+		assert_eq!(node(root).min(base), &20);
+		assert_eq!(node(root).max(base), &70);
+		assert_eq!(node(root).height(base), 3);
+		assert_eq!(node(root).min(left), &10);
+		assert_eq!(node(root).max(left), &70);
+		assert_eq!(node(root).height(left), 4);
+		assert_eq!(node(root).min(right), &10);
+		assert_eq!(node(root).max(right), &90);
+		assert_eq!(node(root).height(right), 4);
+
+		// Removing the extremes shrinks the tree
+		let removed = right.insert_after();
+		let new_root = remove(remove(root, 90, removed), 10, removed);
+		assert_eq!(node(new_root).min(removed), &20);
+		assert_eq!(node(new_root).max(removed), &80);
+		assert_eq!(node(new_root).height(removed), 3);
+	}
+
+	#[test]
+	fn persistent_bst() {
+		let mut versions = vec![PersistentBst::new()];
+		let mut values = Vec::new();
+		for _ in 0..100 {
+			let value = fastrand::u64(..200);
+			versions.push(versions.last().unwrap().insert(value).unwrap());
+			values.push(value);
+		}
+		for (i, version) in versions.iter().enumerate() {
+			for value in 0..200 {
+				assert_eq!(version.contains(&value), values[..i].contains(&value));
+			}
+		}
+	}
+
+	#[test]
+	fn persistent_bst_insert_old() {
+		let tree = PersistentBst::new();
+		let one = tree.insert(1).unwrap();
+		assert!(tree.insert(2).is_none());
+		let two = one.insert(2).unwrap();
+		assert!(one.insert(3).is_none());
+		assert!(!tree.contains(&1));
+		assert!(one.contains(&1) && !one.contains(&2));
+		assert!(two.contains(&1) && two.contains(&2));
+	}
 }
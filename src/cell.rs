@@ -1,20 +1,15 @@
-use std::{collections::BTreeMap, ptr::NonNull};
+use std::{collections::BTreeMap, ops::Bound::Excluded, ptr::NonNull};
 
 use crate::version::{PartialVersion, Version};
 
 enum OwnedOrPointer<T: ?Sized> {
-	Owned(Box<T>),
+	// The owned value is stored as a pointer from `Box::into_raw` instead of a box, since moving
+	// a box asserts that it is unique, which would invalidate the pointers to the value. The
+	// value is freed when the cell is dropped.
+	Owned(NonNull<T>),
 	Pointer(Option<NonNull<T>>),
 }
 
-// TODO: We need to change the api here to instead allow forking creating a new version and then
-// have mutation items on each version. I do not know how to do this without affecting subsequent
-// version, as we want those to not refer to the new but the old value. We can solve this with a
-// changed flag which either owns the old value or points to the previous value, and then we can do
-// path compression to hold our selves within the bounds amortized. Note that version can be used
-// accross different cells and structures, such that a fork is rather a thing on version rather
-// than on the structure and the api and documentation should reflect that.
-
 /// Fully persistent memory cell. Note that all versions passed to functions invoked on a cell must
 /// come from the same version tree. A new version can be created with `Version::new`, and then
 /// relative version can be created with `Version::insert_after` or with functions defined on
@@ -27,6 +22,9 @@ enum OwnedOrPointer<T: ?Sized> {
 // `Vec`.
 pub struct PersistentCell<T: ?Sized> {
 	tree: BTreeMap<PartialVersion, OwnedOrPointer<T>>,
+	// Values replaced by `set`. Forked versions may still point to them, so they are freed when
+	// the cell is dropped.
+	replaced: Vec<NonNull<T>>,
 }
 
 impl<T: ?Sized> Default for PersistentCell<T> {
@@ -39,19 +37,17 @@ impl<T: ?Sized> PersistentCell<T> {
 	pub fn new() -> PersistentCell<T> {
 		PersistentCell {
 			tree: BTreeMap::new(),
+			replaced: Vec::new(),
 		}
 	}
 
 	/// Gets the value in this version. This is the last inserted value in an ancestor of this
 	/// version. Returns None if this version is from before the first version of the tree.
 	pub fn get(&self, version: Version) -> Option<&T> {
-		match self.tree.range(..=version.primary).last()?.1 {
-			OwnedOrPointer::Owned(v) => Some(v),
-			// SAFETY: the pointer points to a value in the tree as it is constructed
-			// in `get_actual`. Values are never removed from the tree and the values
-			// are stored in a box so this pointer is always valid.
-			OwnedOrPointer::Pointer(v) => unsafe { v.map(|ptr| ptr.as_ref()) },
-		}
+		// SAFETY: the pointer points to a value owned by the tree as it is constructed
+		// in `get_pointer`. Values are never removed from the tree and the values
+		// are stored in a box so this pointer is always valid.
+		unsafe { self.get_pointer(version).map(|ptr| ptr.as_ref()) }
 	}
 
 	/// Gets a mutable reference to the value for this version. Returns None if there is no
@@ -60,14 +56,28 @@ impl<T: ?Sized> PersistentCell<T> {
 	/// versions in the future.
 	pub fn get_mut(&mut self, version: Version) -> Option<&mut T> {
 		match self.tree.range_mut(..=version.primary).last()?.1 {
-			OwnedOrPointer::Owned(v) => Some(v),
+			// SAFETY: the value is owned by the tree and we have a mutable reference to self,
+			// so no other references to the value exist.
+			OwnedOrPointer::Owned(v) => Some(unsafe { v.as_mut() }),
 			_ => None,
 		}
 	}
 
+	/// Gets a mutable reference to the value of the nearest ancestor which owns a value, which is
+	/// the value returned by `get`. Returns None if this version is from before the first version
+	/// of the tree. Note that mutating this element mutates it for all versions which inherit the
+	/// value from that ancestor.
+	pub fn get_mut_ancestor(&mut self, version: Version) -> Option<&mut T> {
+		// SAFETY: the pointer points to a value owned by the tree, see `get`. We have a mutable
+		// reference to self, so no other references to the value exist.
+		unsafe { self.get_pointer(version).map(|mut ptr| ptr.as_mut()) }
+	}
+
 	/// Inserts a new value in a new version after the given version.
 	pub fn insert_after(&mut self, version: Version, value: Box<T>) -> Version {
 		let new_version = version.insert_after();
+		// SAFETY: The pointer is valid as it comes from a box
+		let value = unsafe { NonNull::new_unchecked(Box::into_raw(value)) };
 		self.tree
 			.insert(new_version.primary, OwnedOrPointer::Owned(value));
 		self.tree.insert(
@@ -77,17 +87,100 @@ impl<T: ?Sized> PersistentCell<T> {
 		new_version
 	}
 
+	/// Creates a new version after the given version. The new version keeps the value the given
+	/// version has now, even if the value of the given version is set afterwards. Note that the
+	/// version can be used in other cells and structures like any other version.
+	pub fn fork(&mut self, version: Version) -> Version {
+		let new_version = version.insert_after();
+		let value = self.get_pointer(version);
+		self.tree
+			.insert(new_version.primary, OwnedOrPointer::Pointer(value));
+		self.tree
+			.insert(new_version.secondary, OwnedOrPointer::Pointer(value));
+		new_version
+	}
+
+	/// Sets the value of this version. Versions which are not descendants of this version are
+	/// unaffected, and so are descendants created with `fork` or `insert_after`, as they keep the
+	/// value they had when they were created. Other descendants see the new value. Runs in time
+	/// linear in the number of values stored in descendants of this version.
+	pub fn set(&mut self, version: Version, value: Box<T>) {
+		let old = self.get_pointer(version);
+		// SAFETY: The pointer is valid as it comes from a box
+		let value = unsafe { NonNull::new_unchecked(Box::into_raw(value)) };
+		// Every value is stored with an entry in the primary and secondary version of its
+		// version, so the entries of the descendants nest. The secondary entries of the outermost
+		// descendants hold the value of this version after their subtree, so they are set as well.
+		let mut open = Vec::new();
+		let descendants = (Excluded(version.primary), Excluded(version.secondary));
+		for (&key, entry) in self.tree.range_mut(descendants) {
+			if open
+				.last()
+				.is_some_and(|&primary| Version::is_secondary_of(key, primary))
+			{
+				open.pop();
+				if let (true, OwnedOrPointer::Pointer(pointer)) = (open.is_empty(), entry) {
+					*pointer = Some(value);
+				}
+			} else {
+				open.push(key);
+			}
+		}
+		if let Some(OwnedOrPointer::Owned(replaced)) = self
+			.tree
+			.insert(version.primary, OwnedOrPointer::Owned(value))
+		{
+			self.replaced.push(replaced);
+		}
+		self.tree
+			.entry(version.secondary)
+			.or_insert(OwnedOrPointer::Pointer(old));
+	}
+
+	/// Returns the values stored in the cell and the versions they were inserted in, in ascending
+	/// version order.
+	pub fn entries(&self) -> impl Iterator<Item = (PartialVersion, &T)> {
+		self.tree.iter().filter_map(|(version, value)| match value {
+			// SAFETY: the value is owned by the tree, see `get`.
+			OwnedOrPointer::Owned(v) => Some((*version, unsafe { v.as_ref() })),
+			OwnedOrPointer::Pointer(_) => None,
+		})
+	}
+
 	/// Get the version identifier of the last version. Really the dual should just have a
 	/// pointer to the value but that is unsafe without Rc which is needlessly slow.
 	fn get_pointer(&self, version: Version) -> Option<NonNull<T>> {
 		match self.tree.range(..=version.primary).last() {
-			Some((_, OwnedOrPointer::Owned(v))) => Some(NonNull::from(v as &T)),
+			Some((_, OwnedOrPointer::Owned(v))) => Some(*v),
 			Some((_, OwnedOrPointer::Pointer(v))) => *v,
 			None => None,
 		}
 	}
 }
 
+impl<T: Clone> PersistentCell<T> {
+	/// Gets a clone of the value in this version. Returns None if this version is from before the
+	/// first version of the tree.
+	pub fn get_cloned(&self, version: Version) -> Option<T> {
+		self.get(version).cloned()
+	}
+}
+
+impl<T: ?Sized> Drop for PersistentCell<T> {
+	fn drop(&mut self) {
+		for value in self.tree.values() {
+			if let OwnedOrPointer::Owned(v) = value {
+				// SAFETY: the pointer comes from `Box::into_raw` and is only owned by this entry.
+				drop(unsafe { Box::from_raw(v.as_ptr()) });
+			}
+		}
+		for v in &self.replaced {
+			// SAFETY: the pointer was owned by an entry before it was replaced.
+			drop(unsafe { Box::from_raw(v.as_ptr()) });
+		}
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use crate::version::Version;
@@ -195,4 +288,171 @@ mod test {
 			assert_eq!(cell2.get(version), value2.as_ref());
 		}
 	}
+
+	#[test]
+	fn get_mut_ancestor() {
+		let mut cell = PersistentCell::new();
+		let root = Version::new();
+		let a = cell.insert_after(root, Box::new(1));
+		let fork = a.insert_after();
+		let b = cell.insert_after(a, Box::new(2));
+		assert_eq!(cell.get(fork), Some(&1));
+		assert_eq!(cell.get_mut(fork), None);
+		*cell.get_mut_ancestor(fork).unwrap() = 10;
+		assert_eq!(cell.get(fork), Some(&10));
+		assert_eq!(cell.get(a), Some(&10));
+		assert_eq!(cell.get(b), Some(&2));
+		*cell.get_mut_ancestor(b).unwrap() = 20;
+		assert_eq!(cell.get(b), Some(&20));
+		assert_eq!(cell.get(a), Some(&10));
+		assert_eq!(cell.get_mut_ancestor(root), None);
+	}
+
+	#[test]
+	fn drop_stress() {
+		struct Counted<'a>(&'a std::cell::Cell<usize>);
+
+		impl Drop for Counted<'_> {
+			fn drop(&mut self) {
+				self.0.set(self.0.get() + 1);
+			}
+		}
+
+		// Miri is too slow to run the full test in reasonable time.
+		let count = if cfg!(miri) { 1000 } else { 10000 };
+		let dropped = std::cell::Cell::new(0);
+		let mut cell = PersistentCell::new();
+		let mut versions = vec![Version::new()];
+		for _ in 0..count {
+			let version = versions[fastrand::usize(..versions.len())];
+			versions.push(cell.insert_after(version, Box::new(Counted(&dropped))));
+		}
+		for &version in &versions[1..] {
+			assert!(cell.get(version).is_some());
+		}
+		assert_eq!(dropped.get(), 0);
+		drop(cell);
+		assert_eq!(dropped.get(), count);
+	}
+
+	#[test]
+	fn get_cloned() {
+		let mut cell = PersistentCell::new();
+		let mut versions = vec![Version::new()];
+		for i in 0..50 {
+			let version = versions[fastrand::usize(..versions.len())];
+			versions.push(cell.insert_after(version, Box::new(i.to_string())));
+		}
+		for &version in &versions {
+			assert_eq!(cell.get_cloned(version), cell.get(version).cloned());
+		}
+		assert_eq!(cell.get_cloned(versions[0]), None);
+	}
+
+	#[test]
+	fn entries() {
+		let mut cell = PersistentCell::new();
+		let root = Version::new();
+		let a = cell.insert_after(root, Box::new(1));
+		let b = cell.insert_after(root, Box::new(2));
+		let c = cell.insert_after(a, Box::new(3));
+		assert_eq!(cell.entries().count(), 3);
+		let entries: Vec<_> = cell.entries().collect();
+		assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+		for (version, value) in [(a, 1), (b, 2), (c, 3)] {
+			assert!(entries.contains(&(version.primary, &value)));
+		}
+	}
+
+	#[test]
+	fn fork_isolation() {
+		let mut cell = PersistentCell::new();
+		let root = cell.insert_after(Version::new(), Box::new(0));
+		let a = cell.fork(root);
+		let b = cell.fork(root);
+		assert_eq!(cell.get(a), Some(&0));
+		assert_eq!(cell.get(b), Some(&0));
+		cell.set(a, Box::new(1));
+		cell.set(b, Box::new(2));
+		assert_eq!(cell.get(root), Some(&0));
+		assert_eq!(cell.get(a), Some(&1));
+		assert_eq!(cell.get(b), Some(&2));
+		let aa = cell.fork(a);
+		cell.set(a, Box::new(3));
+		assert_eq!(cell.get(a), Some(&3));
+		assert_eq!(cell.get(aa), Some(&1));
+		cell.set(root, Box::new(4));
+		assert_eq!(cell.get(root), Some(&4));
+		assert_eq!(cell.get(a), Some(&3));
+		assert_eq!(cell.get(b), Some(&2));
+		let other = root.insert_after();
+		assert_eq!(cell.get(other), Some(&4));
+		cell.set(other, Box::new(5));
+		assert_eq!(cell.get(other), Some(&5));
+		assert_eq!(cell.get(root), Some(&4));
+	}
+
+	#[test]
+	fn set_plain_descendant() {
+		let mut cell = PersistentCell::new();
+		let root = Version::new();
+		let a = cell.insert_after(root, Box::new(1));
+		let plain = a.insert_after();
+		let b = cell.insert_after(a, Box::new(2));
+		let fork = cell.fork(a);
+		let nested = cell.insert_after(plain, Box::new(3));
+		let after_nested = plain.insert_after();
+		cell.set(a, Box::new(5));
+		assert_eq!(cell.get(a), Some(&5));
+		assert_eq!(cell.get(plain), Some(&5));
+		assert_eq!(cell.get(after_nested), Some(&5));
+		assert_eq!(cell.get(b), Some(&2));
+		assert_eq!(cell.get(fork), Some(&1));
+		assert_eq!(cell.get(nested), Some(&3));
+		// Setting a plain descendant stores its value in its own entries
+		cell.set(plain, Box::new(6));
+		cell.set(a, Box::new(7));
+		assert_eq!(cell.get(plain), Some(&6));
+		assert_eq!(cell.get(after_nested), Some(&6));
+		assert_eq!(cell.get(a.insert_after()), Some(&7));
+		assert_eq!(cell.get(root), None);
+	}
+
+	#[test]
+	fn fork_random() {
+		// A version either has its own value or follows the value of the version at the index
+		enum Model {
+			Own(usize),
+			Follow(usize),
+		}
+		fn resolve(models: &[(Version, Model)], mut k: usize) -> usize {
+			loop {
+				match models[k].1 {
+					Model::Own(value) => return value,
+					Model::Follow(parent) => k = parent,
+				}
+			}
+		}
+		let mut cell = PersistentCell::new();
+		let mut versions = vec![(
+			cell.insert_after(Version::new(), Box::new(0)),
+			Model::Own(0),
+		)];
+		for i in 1..500 {
+			let k = fastrand::usize(..versions.len());
+			let version = versions[k].0;
+			match fastrand::usize(..4) {
+				0 => versions.push((cell.fork(version), Model::Own(resolve(&versions, k)))),
+				1 => versions.push((cell.insert_after(version, Box::new(i)), Model::Own(i))),
+				2 => versions.push((version.insert_after(), Model::Follow(k))),
+				_ => {
+					cell.set(version, Box::new(i));
+					versions[k].1 = Model::Own(i);
+				}
+			}
+		}
+		for (k, &(version, _)) in versions.iter().enumerate() {
+			assert_eq!(cell.get(version), Some(&resolve(&versions, k)));
+		}
+	}
 }
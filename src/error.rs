@@ -0,0 +1,24 @@
+use core::fmt;
+
+/// Errors returned by the fallible operations of the persistent data structures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The index was out of bounds for the length of the version.
+	IndexOutOfBounds { index: usize, len: usize },
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::IndexOutOfBounds { index, len } => {
+				write!(
+					f,
+					"Index out of bounds. Index was {} len was {}",
+					index, len
+				)
+			}
+		}
+	}
+}
+
+impl std::error::Error for Error {}
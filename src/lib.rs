@@ -1,82 +1,875 @@
+#[allow(dead_code)]
 mod fully;
 pub mod version;
 pub mod link;
 pub mod binary_tree;
 pub mod cell;
 pub mod vec;
+pub mod sync;
 pub(crate) mod util;
+mod error;
 
-use std::{num::NonZero, ptr::NonNull, rc::Rc};
+pub use error::Error;
+#[cfg(feature = "alloc-stats")]
+pub use util::{alloc_count, free_count, reset_alloc_count};
 
+use std::{
+	collections::{BTreeMap, HashMap, HashSet, VecDeque},
+	fmt,
+	hash::{Hash, Hasher},
+	marker::PhantomData,
+	ops::{Index, Range},
+	ptr::NonNull,
+	rc::Rc,
+};
+
+use version::{PartialVersion, Version};
+
+/// Persistent doubly linked list. Every mutating operation returns a new handle for a new version
+/// and leaves the handle it was called on untouched. Versions are taken from a version tree, so
+/// mutating an old handle creates a new branch of the history without affecting the versions that
+/// were created from it earlier.
+///
+/// The list is neither `Send` nor `Sync`, since creating a version writes to structure shared with
+/// every other handle of the list. See `sync::ArcPersistentLinkedList` for a thread safe list.
 pub struct PersistenLinkedList<T> {
 	value: Option<NonNull<PersistentLinkedListInner<T>>>,
-	version: usize,
+	tail: Option<NonNull<PersistentLinkedListInner<T>>>,
+	version: Version,
+
+	// The number of elements of this handle. Handles of the same version can have different
+	// lengths, as `tail` reuses the version of the list.
+	len: usize,
+
+	// Shared by the handles of this version, such that it can be checked whether this is the only
+	// handle of the version.
+	handles: Rc<()>,
 }
 
 struct PersistentLinkedListInner<T> {
 	value: Rc<T>,
 	next: PersistentLinkedListPointer<T>,
 	prev: PersistentLinkedListPointer<T>,
-	copy: Option<NonNull<PersistentLinkedListInner<T>>>,
 }
 
+/// Fat node pointer. Stores the pointer for every version in which it was changed. Reading a
+/// version resolves to the last change at or before that version.
 struct PersistentLinkedListPointer<T> {
-	original_version: usize,
-	original: Option<NonNull<PersistentLinkedListInner<T>>>,
-	new_version: Option<NonZero<usize>>,
-	new: Option<NonNull<PersistentLinkedListInner<T>>>,
+	tree: BTreeMap<PartialVersion, Option<NonNull<PersistentLinkedListInner<T>>>>,
+}
+
+impl<T> Default for PersistenLinkedList<T> {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl<T> PersistenLinkedList<T> {
 	pub fn new() -> PersistenLinkedList<T> {
 		PersistenLinkedList {
 			value: None,
-			version: 0,
+			tail: None,
+			version: Version::new(),
+			len: 0,
+			handles: Rc::new(()),
 		}
 	}
 
+	/// The version of this handle.
+	pub fn version(&self) -> Version {
+		self.version
+	}
+
 	pub fn get(&self, index: usize) -> Option<&T> {
 		get_on_opt(self.value, index, self.version).map(|ptr| unsafe { &*ptr })
 	}
 
+	/// Returns the element at the given index counted from the end of this version, such that
+	/// index 0 is the last element. The list is traversed from the tail, so this runs in O(index)
+	/// time.
+	pub fn get_back(&self, index: usize) -> Option<&T> {
+		let mut node = self.tail?;
+		for _ in 0..index {
+			node = self.prev_of(node)?;
+		}
+		Some(unsafe { node.as_ref() }.value.as_ref())
+	}
+
+	/// Returns a mutable reference to the value at the given index. The value is edited in place
+	/// if no other version or handle can observe it, which is the case for a value inserted in
+	/// this version if this is the only handle of the version and no versions have been created
+	/// from it. Otherwise the value is cloned into a new node in a new version, which this handle
+	/// is moved to. Returns None if the index is out of bounds.
+	pub fn get_mut(&mut self, index: usize) -> Option<&mut T>
+	where
+		T: Clone,
+	{
+		let (prev, node) = neighbours_on_opt(self.value, index, self.version)?;
+		let mut node = node?;
+		if !self.is_exclusive(node) {
+			let next = unsafe { node.as_ref() }.next.get(self.version);
+			let value = Rc::new(unsafe { node.as_ref() }.value.as_ref().clone());
+			let (list, new_node) = self.insert_between(prev, next, value, self.len());
+			*self = list;
+			node = new_node;
+		}
+		// SAFETY: No other version or handle can reach the node, and the value is not shared so
+		// get_mut always returns Some.
+		Rc::get_mut(&mut unsafe { node.as_mut() }.value)
+	}
+
+	/// Returns true if the node and its value can only be observed through this handle.
+	fn is_exclusive(&self, node: NonNull<PersistentLinkedListInner<T>>) -> bool {
+		let node = unsafe { node.as_ref() };
+		Rc::strong_count(&self.handles) == 1
+			&& self.version.is_leaf()
+			&& node.prev.tree.keys().next() == Some(&self.version.primary)
+			&& Rc::strong_count(&node.value) == 1
+	}
+
+	/// Inserts the value at the given index in a new version. Returns None if the index is
+	/// greater than the length of the list. Can be called on any handle, also ones that have
+	/// already been mutated, in which case a new branch is created.
 	pub fn insert(&self, index: usize, value: T) -> Option<PersistenLinkedList<T>> {
-		match self.value {
-			Some(_) => insert_on_opt(self.value, index, value, self.version + 1).map(|ptr| {
-				PersistenLinkedList {
-					value: Some(ptr),
-					version: self.version + 1,
-				}
-			}),
-			None => (index == 0).then(|| {
-				let inner = PersistentLinkedListInner::alloc(Rc::new(value), self.version + 1);
-				PersistenLinkedList {
-					value: Some(inner),
-					version: self.version + 1,
-				}
-			}),
+		self.try_insert(index, value).ok()
+	}
+
+	/// Inserts the value at the given index in a new version. Returns an error if the index is
+	/// greater than the length of the list.
+	pub fn try_insert(&self, index: usize, value: T) -> Result<PersistenLinkedList<T>, Error> {
+		let (prev, next) = neighbours_on_opt(self.value, index, self.version)
+			.ok_or_else(|| self.index_out_of_bounds(index))?;
+		let len = self.len() + 1;
+		Ok(self.insert_between(prev, next, Rc::new(value), len).0)
+	}
+
+	/// Inserts the value at the front in a new version. The list is not traversed, so this runs
+	/// in O(1) time apart from looking up the length.
+	pub fn prepend(&self, value: T) -> PersistenLinkedList<T> {
+		self.insert_between(None, self.value, Rc::new(value), self.len() + 1)
+			.0
+	}
+
+	/// Removes the value at the given index in a new version. Returns None if the index is out of
+	/// bounds.
+	pub fn remove(&self, index: usize) -> Option<PersistenLinkedList<T>> {
+		self.try_remove(index).ok()
+	}
+
+	/// Removes the value at the given index in a new version. Returns an error if the index is
+	/// out of bounds.
+	pub fn try_remove(&self, index: usize) -> Result<PersistenLinkedList<T>, Error> {
+		match neighbours_on_opt(self.value, index, self.version) {
+			Some((prev, Some(node))) => Ok(self.remove_node(prev, node).0),
+			_ => Err(self.index_out_of_bounds(index)),
 		}
 	}
 
-	pub fn crawl_debug(&self) {
-		crawl_debug(self.value, self.version);
+	/// Removes the first element in a new version. Returns the new version and the removed value,
+	/// or None if the list is empty. The value is returned as an Rc so no clone is needed. Runs in
+	/// O(1) time as the list does not have to be traversed.
+	pub fn pop_front(&self) -> Option<(PersistenLinkedList<T>, Rc<T>)> {
+		let head = self.value?;
+		let (list, _) = self.remove_node(None, head);
+		Some((list, unsafe { head.as_ref() }.value.clone()))
+	}
+
+	/// Returns the list without its first element, or None if the list is empty. Runs in O(1)
+	/// time, as the returned handle points at the second element in this version, so no version
+	/// is created. The second element is still linked back to the first element in this version,
+	/// so reading backwards stops at the head of the handle, see `prev_of`.
+	pub fn tail(&self) -> Option<PersistenLinkedList<T>> {
+		let head = self.value?;
+		let next = unsafe { head.as_ref() }.next.get(self.version);
+		Some(PersistenLinkedList {
+			value: next,
+			tail: next.and(self.tail),
+			version: self.version,
+			len: self.len - 1,
+			handles: self.handles.clone(),
+		})
+	}
+
+	/// Returns the node before the node in this version, or None if the node is the head of this
+	/// handle. The head of a handle created by `tail` is still linked back to the elements before
+	/// it in the version, so the head is checked rather than its prev pointer.
+	fn prev_of(
+		&self,
+		node: NonNull<PersistentLinkedListInner<T>>,
+	) -> Option<NonNull<PersistentLinkedListInner<T>>> {
+		if Some(node) == self.value {
+			return None;
+		}
+		unsafe { node.as_ref() }.prev.get(self.version)
+	}
+
+	/// Removes the last element in a new version. Returns the new version and the removed value,
+	/// or None if the list is empty. Runs in O(1) time as the tail is stored in the handle.
+	pub fn pop_back(&self) -> Option<(PersistenLinkedList<T>, Rc<T>)> {
+		let tail = self.tail?;
+		let prev = self.prev_of(tail);
+		let (list, _) = self.remove_node(prev, tail);
+		Some((list, unsafe { tail.as_ref() }.value.clone()))
+	}
+
+	/// Removes the first element in a new version. Returns None if the list is empty. Runs in O(1)
+	/// time. Unlike `tail`, the first element is unlinked in the new version.
+	pub fn remove_first(&self) -> Option<PersistenLinkedList<T>> {
+		self.pop_front().map(|(list, _)| list)
+	}
+
+	/// Removes the last element in a new version. Returns None if the list is empty. Runs in O(1)
+	/// time as the tail is stored in the handle, so the list is not traversed.
+	pub fn remove_last(&self) -> Option<PersistenLinkedList<T>> {
+		self.pop_back().map(|(list, _)| list)
+	}
+
+	/// Replaces the value at the given index in a new version. A single new node is allocated and
+	/// the rest of the structure is shared with this version. Returns None if the index is out of
+	/// bounds.
+	pub fn replace(&self, index: usize, value: T) -> Option<PersistenLinkedList<T>> {
+		let (prev, node) = neighbours_on_opt(self.value, index, self.version)?;
+		let next = unsafe { node?.as_ref() }.next.get(self.version);
+		let len = self.len();
+		Some(self.insert_between(prev, next, Rc::new(value), len).0)
+	}
+
+	/// Replaces the value at the given index with the result of applying the function to it in a
+	/// new version. The list is only traversed once. Returns None if the index is out of bounds,
+	/// in which case the function is not called.
+	pub fn map_at<F: FnOnce(&T) -> T>(&self, index: usize, f: F) -> Option<PersistenLinkedList<T>> {
+		let (prev, node) = neighbours_on_opt(self.value, index, self.version)?;
+		let node = unsafe { node?.as_ref() };
+		let next = node.next.get(self.version);
+		let len = self.len();
+		Some(
+			self.insert_between(prev, next, Rc::new(f(&node.value)), len)
+				.0,
+		)
+	}
+
+	/// Swaps the values at the two indices in a new version. Two new nodes holding the swapped
+	/// values are allocated and the rest of the structure is shared with this version. If the
+	/// indices are equal the new version shares every node with this version. Returns None if
+	/// either index is out of bounds.
+	pub fn swap(&self, i: usize, j: usize) -> Option<PersistenLinkedList<T>> {
+		let (i, j) = (i.min(j), i.max(j));
+		let nodes: Vec<_> = self.nodes().take(j + 1).collect();
+		if nodes.len() <= j {
+			return None;
+		}
+		let version = self.new_version();
+		if i == j {
+			return Some(self.with_ends(self.value, self.tail, version, self.len));
+		}
+		let value =
+			|node: NonNull<PersistentLinkedListInner<T>>| unsafe { node.as_ref() }.value.clone();
+		let node_i = PersistentLinkedListInner::alloc(value(nodes[j]), version, None, None);
+		let node_j = PersistentLinkedListInner::alloc(value(nodes[i]), version, None, None);
+		let prev = i.checked_sub(1).map(|prev| nodes[prev]);
+		let next = unsafe { nodes[j].as_ref() }.next.get(self.version);
+		link(prev, Some(node_i), version);
+		if j == i + 1 {
+			link(Some(node_i), Some(node_j), version);
+		} else {
+			link(Some(node_i), Some(nodes[i + 1]), version);
+			link(Some(nodes[j - 1]), Some(node_j), version);
+		}
+		link(Some(node_j), next, version);
+		let value = if prev.is_some() {
+			self.value
+		} else {
+			Some(node_i)
+		};
+		let tail = if next.is_some() {
+			self.tail
+		} else {
+			Some(node_j)
+		};
+		Some(self.with_ends(value, tail, version, self.len))
+	}
+
+	/// Returns a cursor pointing at the first element of this version. Moving the cursor runs in
+	/// O(1) time, so a sequential scan with the cursor runs in O(n) time unlike repeated calls to
+	/// `get`.
+	pub fn cursor(&self) -> Cursor<T> {
+		Cursor {
+			list: self.clone(),
+			prev: None,
+			current: self.value,
+			index: 0,
+		}
+	}
+
+	/// Returns a cursor pointing at the element at the given index in this version. If the index
+	/// is equal to the length of the list the cursor points at the end of the list. Returns None
+	/// if the index is greater than the length of the list.
+	pub fn cursor_at(&self, index: usize) -> Option<Cursor<T>> {
+		let (prev, current) = neighbours_on_opt(self.value, index, self.version)?;
+		Some(Cursor {
+			list: self.clone(),
+			prev,
+			current,
+			index,
+		})
+	}
+
+	fn index_out_of_bounds(&self, index: usize) -> Error {
+		Error::IndexOutOfBounds {
+			index,
+			len: self.len(),
+		}
+	}
+
+	/// Creates a new list with a new version tree containing the values.
+	fn from_values(values: Vec<Rc<T>>) -> PersistenLinkedList<T> {
+		let empty = PersistenLinkedList::new();
+		let version = empty.new_version();
+		let len = values.len();
+		let (head, tail) = alloc_chain(values.into_iter(), version, None).unzip();
+		empty.with_ends(head, tail, version, len)
+	}
+
+	/// Creates a new version after this version.
+	fn new_version(&self) -> Version {
+		self.version.insert_after()
+	}
+
+	/// Creates a handle of this list with the given head, tail, version and length.
+	fn with_ends(
+		&self,
+		value: Option<NonNull<PersistentLinkedListInner<T>>>,
+		tail: Option<NonNull<PersistentLinkedListInner<T>>>,
+		version: Version,
+		len: usize,
+	) -> PersistenLinkedList<T> {
+		PersistenLinkedList {
+			value,
+			tail,
+			version,
+			len,
+			handles: Rc::new(()),
+		}
+	}
+
+	/// Inserts a new node between the two nodes in a new version with the given length. Any nodes
+	/// between them are unlinked in the new version. Returns the new version and the new node.
+	fn insert_between(
+		&self,
+		prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+		next: Option<NonNull<PersistentLinkedListInner<T>>>,
+		value: Rc<T>,
+		len: usize,
+	) -> (
+		PersistenLinkedList<T>,
+		NonNull<PersistentLinkedListInner<T>>,
+	) {
+		let version = self.new_version();
+		let node = PersistentLinkedListInner::alloc(value, version, prev, next);
+		link(prev, Some(node), version);
+		link(Some(node), next, version);
+		let value = if prev.is_some() {
+			self.value
+		} else {
+			Some(node)
+		};
+		let tail = if next.is_some() {
+			self.tail
+		} else {
+			Some(node)
+		};
+		(self.with_ends(value, tail, version, len), node)
+	}
+
+	/// Removes the node which is directly after prev in a new version. Returns the new version
+	/// and the node after the removed node.
+	fn remove_node(
+		&self,
+		prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+		node: NonNull<PersistentLinkedListInner<T>>,
+	) -> (
+		PersistenLinkedList<T>,
+		Option<NonNull<PersistentLinkedListInner<T>>>,
+	) {
+		let version = self.new_version();
+		let next = unsafe { node.as_ref() }.next.get(self.version);
+		link(prev, next, version);
+		let value = if prev.is_some() { self.value } else { next };
+		let tail = if next.is_some() { self.tail } else { prev };
+		(self.with_ends(value, tail, version, self.len - 1), next)
+	}
+
+	/// Returns a new version containing the elements of this version followed by the elements of
+	/// the other version. The other list may belong to a different version tree, so its nodes can
+	/// not be shared and new nodes are allocated for its elements. The values themselves are
+	/// shared.
+	pub fn concat(&self, other: &PersistenLinkedList<T>) -> PersistenLinkedList<T> {
+		self.append_values(
+			other
+				.nodes()
+				.map(|node| unsafe { node.as_ref() }.value.clone())
+				.collect(),
+		)
+	}
+
+	/// Returns a new version with the items appended to the end of this version. All items are
+	/// added in a single version.
+	pub fn extend<I: IntoIterator<Item = T>>(&self, iter: I) -> PersistenLinkedList<T> {
+		self.append_values(iter.into_iter().map(Rc::new).collect())
+	}
+
+	/// Inserts the items at the given index in a new version. All items are inserted in a single
+	/// version and the list is only traversed once. Returns None if the index is greater than the
+	/// length of the list.
+	pub fn extend_at<I: IntoIterator<Item = T>>(
+		&self,
+		index: usize,
+		iter: I,
+	) -> Option<PersistenLinkedList<T>> {
+		let (prev, next) = neighbours_on_opt(self.value, index, self.version)?;
+		let values: Vec<_> = iter.into_iter().map(Rc::new).collect();
+		let len = self.len() + values.len();
+		Some(self.insert_values_between(prev, next, values, len))
+	}
+
+	/// Appends the values to the end of this version in a new version.
+	fn append_values(&self, values: Vec<Rc<T>>) -> PersistenLinkedList<T> {
+		let len = self.len() + values.len();
+		self.insert_values_between(self.tail, None, values, len)
+	}
+
+	/// Replaces the nodes between the two nodes with new nodes for the values in a new version
+	/// with the given length. Only the two nodes get new pointers, the new nodes are linked to
+	/// each other when they are allocated.
+	fn insert_values_between(
+		&self,
+		prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+		next: Option<NonNull<PersistentLinkedListInner<T>>>,
+		values: Vec<Rc<T>>,
+		len: usize,
+	) -> PersistenLinkedList<T> {
+		let version = self.new_version();
+		let Some((head, tail)) = alloc_chain(values.into_iter(), version, prev) else {
+			link(prev, next, version);
+			let value = if prev.is_some() { self.value } else { next };
+			let tail = if next.is_some() { self.tail } else { prev };
+			return self.with_ends(value, tail, version, len);
+		};
+		link(prev, Some(head), version);
+		link(Some(tail), next, version);
+		let value = if prev.is_some() {
+			self.value
+		} else {
+			Some(head)
+		};
+		let tail = if next.is_some() {
+			self.tail
+		} else {
+			Some(tail)
+		};
+		self.with_ends(value, tail, version, len)
+	}
+
+	/// Replaces the elements in the range with the items in a new version, like `Vec::splice`.
+	/// Only the nodes around the range get new pointers, so the cost does not depend on the
+	/// length of the range. Returns None if the range is out of bounds or its start is after its
+	/// end.
+	pub fn splice<I: IntoIterator<Item = T>>(
+		&self,
+		range: Range<usize>,
+		replacement: I,
+	) -> Option<PersistenLinkedList<T>> {
+		let len = self.len();
+		if range.start > range.end || range.end > len {
+			return None;
+		}
+		let (prev, mut next) = neighbours_on_opt(self.value, range.start, self.version)?;
+		for _ in range.clone() {
+			next = unsafe { next?.as_ref() }.next.get(self.version);
+		}
+		let values: Vec<_> = replacement.into_iter().map(Rc::new).collect();
+		let len = len - range.len() + values.len();
+		Some(self.insert_values_between(prev, next, values, len))
+	}
+
+	/// Returns a new version containing only the elements for which the predicate returns true in
+	/// their original order. All elements are removed in a single version and no nodes are
+	/// allocated, the nodes around every run of removed elements are linked in the new version.
+	pub fn retain<F: Fn(&T) -> bool>(&self, f: F) -> PersistenLinkedList<T> {
+		let nodes: Vec<_> = self
+			.nodes()
+			.map(|node| (node, f(unsafe { node.as_ref() }.value.as_ref())))
+			.collect();
+		let len = nodes.iter().filter(|(_, keep)| *keep).count();
+		let version = self.new_version();
+		let mut head = None;
+		let mut prev = None;
+		let mut removed = false;
+		for (node, keep) in nodes {
+			if !keep {
+				removed = true;
+				continue;
+			}
+			if removed {
+				link(prev, Some(node), version);
+				removed = false;
+			}
+			head.get_or_insert(node);
+			prev = Some(node);
+		}
+		if removed {
+			link(prev, None, version);
+		}
+		self.with_ends(head, prev, version, len)
+	}
+
+	/// Returns a new version with every element equal to the value removed, keeping the order of
+	/// the remaining elements. All elements are removed in a single version, see `retain`.
+	pub fn remove_all(&self, value: &T) -> PersistenLinkedList<T>
+	where
+		T: PartialEq,
+	{
+		self.retain(|x| x != value)
+	}
+
+	/// Returns two new lists containing clones of the elements of this version for which the
+	/// predicate returns true and false respectively, in their original order. The new lists have
+	/// new version trees and no history.
+	pub fn partition<F: Fn(&T) -> bool>(
+		&self,
+		f: F,
+	) -> (PersistenLinkedList<T>, PersistenLinkedList<T>)
+	where
+		T: Clone,
+	{
+		let (left, right): (Vec<_>, Vec<_>) = self
+			.iter()
+			.cloned()
+			.map(Rc::new)
+			.partition(|value| f(value));
+		(
+			PersistenLinkedList::from_values(left),
+			PersistenLinkedList::from_values(right),
+		)
+	}
+
+	/// Returns a new list containing every n-th element of this version, starting with the first
+	/// element. The new list has a new version tree and no history. The values themselves are
+	/// shared with this list.
+	///
+	/// # Panics
+	/// Panics if n is 0.
+	pub fn step_by(&self, n: usize) -> PersistenLinkedList<T> {
+		assert!(n > 0, "step must be non-zero");
+		PersistenLinkedList::from_values(
+			self.nodes()
+				.step_by(n)
+				.map(|node| unsafe { node.as_ref() }.value.clone())
+				.collect(),
+		)
+	}
+
+	/// Inserts the value before the first element greater than it in a new version, such that a
+	/// sorted list stays sorted. Runs in O(n) time.
+	pub fn sorted_insert(&self, value: T) -> PersistenLinkedList<T>
+	where
+		T: Ord,
+	{
+		let index = self.find(|element| *element > value).unwrap_or(self.len());
+		self.try_insert(index, value)
+			.expect("the index is at most the length of the list")
+	}
+
+	/// Returns a new version with the elements of this version in ascending order. The sort is
+	/// stable and runs in O(n log n) time. The sorted elements are allocated as one chain of new
+	/// nodes in a single new version, but the values themselves are shared with this version.
+	pub fn sort(&self) -> PersistenLinkedList<T>
+	where
+		T: Ord + Clone,
+	{
+		let mut values = self.to_vec_rc();
+		values.sort();
+		let version = self.new_version();
+		let (head, tail) = alloc_chain(values.into_iter(), version, None).unzip();
+		self.with_ends(head, tail, version, self.len)
+	}
+
+	/// Returns true if the elements of this version are in ascending order.
+	pub fn is_sorted(&self) -> bool
+	where
+		T: Ord,
+	{
+		self.iter().is_sorted()
+	}
+
+	/// Returns the operations that turn this version into the other version when applied in
+	/// order. Nodes are compared by identity rather than by value, so two versions of the same
+	/// list are diffed by the nodes they share in O(n log n) time. Versions of different lists do
+	/// not share nodes, so every element is removed and inserted.
+	pub fn diff<'a>(&'a self, other: &'a PersistenLinkedList<T>) -> Vec<DiffOp<'a, T>> {
+		let theirs: Vec<_> = other.nodes().collect();
+		let positions: HashMap<_, _> = theirs
+			.iter()
+			.enumerate()
+			.map(|(j, &node)| (node, j))
+			.collect();
+		let ours: Vec<_> = self
+			.nodes()
+			.map(|node| positions.get(&node).copied())
+			.collect();
+		let mut ops = Vec::new();
+		let (mut i, mut j, mut index) = (0, 0, 0);
+		// The nodes in the longest increasing subsequence are kept, everything between them is
+		// removed or inserted.
+		let kept = longest_increasing(&ours);
+		for (next_i, next_j) in kept.into_iter().chain([(ours.len(), theirs.len())]) {
+			ops.extend((i..next_i).map(|_| DiffOp::Removed { index }));
+			for node in &theirs[j..next_j] {
+				let value = unsafe { node.as_ref() }.value.as_ref();
+				ops.push(DiffOp::Inserted { index, value });
+				index += 1;
+			}
+			(i, j, index) = (next_i + 1, next_j + 1, index + 1);
+		}
+		ops
+	}
+
+	/// Returns true if the value is in this version of the list.
+	pub fn contains(&self, value: &T) -> bool
+	where
+		T: PartialEq,
+	{
+		self.iter().any(|v| v == value)
+	}
+
+	/// Returns the index of the first occurrence of the value in this version of the list.
+	pub fn position(&self, value: &T) -> Option<usize>
+	where
+		T: PartialEq,
+	{
+		self.iter().position(|v| v == value)
+	}
+
+	/// Returns the index of the first element of this version for which the predicate returns
+	/// true. The predicate is not called on the elements after it.
+	pub fn find<F: FnMut(&T) -> bool>(&self, pred: F) -> Option<usize> {
+		self.iter().position(pred)
+	}
+
+	/// Returns a new list with the function applied to every element of this version. The new
+	/// list has a new version tree and has no history.
+	pub fn map<U, F: Fn(&T) -> U>(&self, f: F) -> PersistenLinkedList<U> {
+		PersistenLinkedList::from_values(self.iter().map(|value| Rc::new(f(value))).collect())
+	}
+
+	/// Returns a new list with the elements of this version in reverse order. The new list has a
+	/// new version tree and has no history connecting it to this list. New nodes are allocated
+	/// for every element, but the values themselves are shared, so this runs in O(n) time.
+	pub fn reverse(&self) -> PersistenLinkedList<T> {
+		let mut values: Vec<_> = self
+			.nodes()
+			.map(|node| unsafe { node.as_ref() }.value.clone())
+			.collect();
+		values.reverse();
+		PersistenLinkedList::from_values(values)
+	}
+
+	/// Returns a new version of this list with the elements in reverse order. Unlike `reverse`
+	/// the new version shares the nodes with this version and is part of its history. Every node
+	/// gets its next and prev pointers swapped in the new version, so this runs in O(n log m)
+	/// time.
+	pub fn reversed(&self) -> PersistenLinkedList<T> {
+		let nodes: Vec<_> = self.nodes().collect();
+		let version = self.new_version();
+		for &node in &nodes {
+			let prev = self.prev_of(node);
+			let node = unsafe { &mut *node.as_ptr() };
+			let next = node.next.get(self.version);
+			node.next.set(version, prev);
+			node.prev.set(version, next);
+		}
+		self.with_ends(
+			nodes.last().copied(),
+			nodes.first().copied(),
+			version,
+			self.len,
+		)
+	}
+
+	/// Creates a new list with a new version tree containing the elements of the vec. All elements
+	/// are added in a single version in O(n) time.
+	pub fn from_vec(v: Vec<T>) -> PersistenLinkedList<T> {
+		PersistenLinkedList::from_values(v.into_iter().map(Rc::new).collect())
+	}
+
+	/// Returns the number of elements in this version. Runs in O(1) time, as the length is stored
+	/// in the handle.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Returns true if this version of the list has no elements.
+	pub fn is_empty(&self) -> bool {
+		self.first().is_none()
+	}
+
+	/// Returns the first element of this version. Runs in O(1) time.
+	pub fn first(&self) -> Option<&T> {
+		self.value
+			.map(|node| unsafe { node.as_ref() }.value.as_ref())
+	}
+
+	/// Returns the last element of this version. Runs in O(1) time as the tail is stored in the
+	/// handle.
+	pub fn last(&self) -> Option<&T> {
+		self.tail
+			.map(|node| unsafe { node.as_ref() }.value.as_ref())
+	}
+
+	/// Returns a vec containing clones of the elements of this version.
+	pub fn to_vec(&self) -> Vec<T>
+	where
+		T: Clone,
+	{
+		self.iter().cloned().collect()
+	}
+
+	/// Returns a vec containing the elements of this version. The values are shared with the list
+	/// so no clone is needed.
+	pub fn to_vec_rc(&self) -> Vec<Rc<T>> {
+		self.nodes()
+			.map(|node| unsafe { node.as_ref() }.value.clone())
+			.collect()
+	}
+
+	/// Returns a vec containing references to the elements of this version.
+	pub fn to_vec_ref(&self) -> Vec<&T> {
+		self.iter().collect()
+	}
+
+	/// Returns an iterator over the elements of this version.
+	pub fn iter(&self) -> Iter<'_, T> {
+		self.iter_from_node(self.value)
+	}
+
+	/// Returns a view of this version, which can be indexed with the index operator and iterated
+	/// from both ends.
+	pub fn view(&self) -> PersistentLinkedListView<'_, T> {
+		PersistentLinkedListView { list: self }
+	}
+
+	/// Returns an iterator over all contiguous windows of n elements of this version, like
+	/// `slice::windows`. The list is only traversed once. There are no windows if the list is
+	/// shorter than n.
+	///
+	/// # Panics
+	/// Panics if n is 0.
+	pub fn windows(&self, n: usize) -> Windows<'_, T> {
+		assert!(n > 0, "window size must be non-zero");
+		Windows {
+			iter: self.iter(),
+			window: VecDeque::with_capacity(n),
+			size: n,
+		}
+	}
+
+	/// Returns an index over this version which makes random access run in O(√n) time instead of
+	/// O(index) time. Building the index traverses the list once and stores a pointer to every
+	/// √n-th node, so it costs O(√n) memory. The index borrows this version, which can not change,
+	/// so it stays valid while new versions of the list are created.
+	pub fn skip_index(&self) -> SkipIndex<'_, T> {
+		let len = self.len();
+		// The stride is the square root rounded up, so there are at most √n checkpoints
+		let root = len.isqrt();
+		let stride = if root * root < len {
+			root + 1
+		} else {
+			root.max(1)
+		};
+		SkipIndex {
+			list: self,
+			checkpoints: self.nodes().step_by(stride).collect(),
+			stride,
+			len,
+		}
+	}
+
+	/// Returns iterators over the elements before the split index and the elements from the
+	/// split index in this version. Nothing is allocated or copied. Returns None if the split
+	/// index is greater than the length of the list.
+	pub fn segments(&self, split: usize) -> Option<(impl Iterator<Item = &T>, Iter<'_, T>)> {
+		let (_, next) = neighbours_on_opt(self.value, split, self.version)?;
+		Some((self.iter().take(split), self.iter_from_node(next)))
+	}
+
+	/// Returns an iterator over the elements in the range from start to end in this version. The
+	/// list is only traversed once. The end is clamped to the length of the list.
+	pub fn get_range(&self, start: usize, end: usize) -> impl Iterator<Item = &T> {
+		let next = neighbours_on_opt(self.value, start, self.version).and_then(|(_, next)| next);
+		self.iter_from_node(next).take(end.saturating_sub(start))
+	}
+
+	fn iter_from_node(&self, next: Option<NonNull<PersistentLinkedListInner<T>>>) -> Iter<'_, T> {
+		Iter {
+			nodes: Nodes {
+				next,
+				version: self.version,
+			},
+			marker: PhantomData,
+		}
+	}
+
+	fn nodes(&self) -> Nodes<T> {
+		Nodes {
+			next: self.value,
+			version: self.version,
+		}
+	}
+
+	/// Returns statistics about the memory used by the history of this list. The nodes are found
+	/// by following the pointers of every version from the nodes of this version, so nodes which
+	/// were never linked to those nodes, such as the nodes of a list before it was sorted, are not
+	/// counted. Runs in O(k log k) time where k is the number of pointer entries.
+	pub fn stats(&self) -> Stats {
+		let mut stats = Stats::default();
+		let mut visited = HashSet::new();
+		let mut stack: Vec<_> = self.nodes().collect();
+		while let Some(node) = stack.pop() {
+			if !visited.insert(node) {
+				continue;
+			}
+			let node = unsafe { node.as_ref() };
+			let entries = node.next.tree.len() + node.prev.tree.len();
+			stats.nodes += 1;
+			stats.pointer_entries += entries;
+			// A node gets two entries for each pointer when it is allocated
+			if entries > 4 {
+				stats.modified_nodes += 1;
+			}
+			stack.extend(
+				node.next
+					.tree
+					.values()
+					.chain(node.prev.tree.values())
+					.flatten(),
+			);
+		}
+		stats
 	}
-}
 
-fn crawl_debug<T>(opt: Option<NonNull<PersistentLinkedListInner<T>>>, version: usize) {
-	if let Some(ptr) = opt {
-		let ptr = unsafe { ptr.as_ref() };
-		eprintln!("Node {:?} {{", ptr as *const _);
-		eprintln!("\tprev: {:?}", ptr.prev.get(version).map(|p| unsafe { p.as_ref() } as *const _).unwrap_or(std::ptr::null()));
-		eprintln!("\tnext: {:?}", ptr.next.get(version).map(|p| unsafe { p.as_ref() } as *const _).unwrap_or(std::ptr::null()));
-		eprintln!("}}");
-		crawl_debug(ptr.next.get(version), version);
+	/// Prints the elements of this version to stderr.
+	pub fn crawl_debug(&self)
+	where
+		T: fmt::Debug,
+	{
+		eprintln!("{:?}", self);
 	}
 }
 
 fn get_on_opt<T>(
 	opt: Option<NonNull<PersistentLinkedListInner<T>>>,
 	index: usize,
-	version: usize,
+	version: Version,
 ) -> Option<*const T> {
 	let ptr = opt?;
 	let val = unsafe { ptr.as_ref() };
@@ -87,180 +880,597 @@ fn get_on_opt<T>(
 	}
 }
 
-fn insert_on_opt<T>(
+/// Makes the two nodes adjacent in the version.
+fn link<T>(
+	prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+	next: Option<NonNull<PersistentLinkedListInner<T>>>,
+	version: Version,
+) {
+	if let Some(mut prev) = prev {
+		unsafe { prev.as_mut() }.next.set(version, next);
+	}
+	if let Some(mut next) = next {
+		unsafe { next.as_mut() }.prev.set(version, prev);
+	}
+}
+
+/// Returns the nodes that will be directly before and after a node inserted at the index. Returns
+/// None if the index is greater than the length of the list.
+#[allow(clippy::type_complexity)]
+fn neighbours_on_opt<T>(
 	opt: Option<NonNull<PersistentLinkedListInner<T>>>,
 	index: usize,
-	value: T,
-	version: usize,
-) -> Option<NonNull<PersistentLinkedListInner<T>>> {
-	let ptr = unsafe { opt?.as_mut() };
-	if index == 0 {
-		let mut new_node = PersistentLinkedListInner::alloc(Rc::new(value), version);
-		let new_node_ptr = unsafe { new_node.as_mut() };
-		new_node_ptr.set_ptr(version, opt, |l| &mut l.next);
-		new_node_ptr.set_ptr(version, ptr.prev.get(version), |l| &mut l.prev);
-		new_node_ptr.cascade_ptrs(version);
-		Some(new_node)
-	} else {
-		let next = ptr.next.get(version - 1);
-		if next.is_none() && index == 1 {
-			let mut new_node = PersistentLinkedListInner::alloc(Rc::new(value), version);
-			let new_node_ptr = unsafe { new_node.as_mut() };
-			new_node_ptr.set_ptr(version, opt, |l| &mut l.prev);
-			new_node_ptr.cascade_ptrs(version);
+	version: Version,
+) -> Option<(
+	Option<NonNull<PersistentLinkedListInner<T>>>,
+	Option<NonNull<PersistentLinkedListInner<T>>>,
+)> {
+	let mut prev = None;
+	let mut next = opt;
+	for _ in 0..index {
+		let ptr = next?;
+		prev = Some(ptr);
+		next = unsafe { ptr.as_ref() }.next.get(version);
+	}
+	Some((prev, next))
+}
+
+/// Memory usage of the history of a list, see `PersistenLinkedList::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+	/// The number of nodes.
+	pub nodes: usize,
+	/// The number of nodes which have had a pointer changed after they were allocated.
+	pub modified_nodes: usize,
+	/// The number of entries in the fat node pointers of the nodes.
+	pub pointer_entries: usize,
+}
+
+/// An edit of a list, see `PersistenLinkedList::diff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffOp<'a, T> {
+	/// The value is inserted at the index.
+	Inserted { index: usize, value: &'a T },
+	/// The element at the index is removed.
+	Removed { index: usize },
+}
+
+/// Returns the longest strictly increasing subsequence of the values as pairs of index and value.
+/// Runs in O(n log n) time.
+fn longest_increasing(values: &[Option<usize>]) -> Vec<(usize, usize)> {
+	// tails[k] is the smallest value which ends an increasing subsequence of length k + 1
+	let mut tails: Vec<(usize, usize)> = Vec::new();
+	let mut prev = vec![None; values.len()];
+	for (i, value) in values.iter().enumerate() {
+		let Some(value) = *value else {
+			continue;
+		};
+		let k = tails.partition_point(|&(_, tail)| tail < value);
+		prev[i] = k.checked_sub(1).map(|k| tails[k]);
+		if k == tails.len() {
+			tails.push((i, value));
 		} else {
-			insert_on_opt(next, index - 1, value, version)?;
+			tails[k] = (i, value);
 		}
-		Some(get_new_version(opt?))
 	}
+	let mut result = Vec::new();
+	let mut current = tails.last().copied();
+	while let Some((i, value)) = current {
+		result.push((i, value));
+		current = prev[i];
+	}
+	result.reverse();
+	result
 }
 
-fn get_new_version<T>(
-	opt: NonNull<PersistentLinkedListInner<T>>,
-) -> NonNull<PersistentLinkedListInner<T>> {
-	unsafe { opt.as_ref() }.copy.unwrap_or(opt)
+/// A position in a version of a list. Edits through the cursor do not traverse the list and return
+/// a new version together with a cursor in the new version. The cursor is not affected by edits in
+/// other versions. The cursor is either at an element or at the end of the list.
+pub struct Cursor<T> {
+	list: PersistenLinkedList<T>,
+	prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+	current: Option<NonNull<PersistentLinkedListInner<T>>>,
+	index: usize,
 }
 
-impl<T> PersistentLinkedListInner<T> {
-	fn alloc(value: Rc<T>, version: usize) -> NonNull<PersistentLinkedListInner<T>> {
-		let ret = PersistentLinkedListInner {
-			value,
-			next: PersistentLinkedListPointer::new(version),
-			prev: PersistentLinkedListPointer::new(version),
-			copy: None,
-		};
-		let b = Box::new(ret);
-		NonNull::from(Box::leak(b))
+impl<T> Cursor<T> {
+	/// The version of the list the cursor is in.
+	pub fn list(&self) -> &PersistenLinkedList<T> {
+		&self.list
 	}
 
-	fn copy(&mut self, value: Rc<T>, version: usize) -> &mut PersistentLinkedListInner<T> {
-		let mut copy = PersistentLinkedListInner::alloc(value, version);
-		let ptr = unsafe { copy.as_mut() };
-		assert!(!ptr.next.update(version, self.next.get(version)));
-		assert!(!ptr.prev.update(version, self.prev.get(version)));
-		self.copy = Some(copy);
-		ptr
-	}
-
-	fn set_ptr(
-		&mut self,
-		version: usize,
-		ptr: Option<NonNull<PersistentLinkedListInner<T>>>,
-		which: fn(&mut PersistentLinkedListInner<T>) -> &mut PersistentLinkedListPointer<T>,
-	) -> Option<&mut PersistentLinkedListInner<T>> {
-		if which(self).get(version) == ptr {
-			None
-		} else if which(self).update(version, ptr) {
-			let copy = self.copy(self.value.clone(), version);
-			assert!(!which(copy).update(version, ptr));
-			Some(copy)
-		} else {
-			assert_eq!(ptr, which(self).get(version));
-			Some(self)
-		}
+	/// The index of the cursor. This is the length of the list if the cursor is at the end.
+	pub fn index(&self) -> usize {
+		self.index
 	}
 
-	fn cascade_ptrs(&self, version: usize) {
-		if let Some(next) = self.next.get(version) {
-			let next = unsafe { get_new_version(next).as_mut() };
-			if let Some(next) = next.set_ptr(version, Some(NonNull::from(self)), |l| &mut l.prev) {
-				next.cascade_ptrs(version);
+	/// The element the cursor points at. Returns None if the cursor is at the end.
+	pub fn current(&self) -> Option<&T> {
+		self.current
+			.map(|node| unsafe { node.as_ref() }.value.as_ref())
+	}
+
+	/// Moves the cursor to the next element. Returns false if the cursor is already at the end.
+	pub fn move_next(&mut self) -> bool {
+		match self.current {
+			Some(current) => {
+				self.prev = Some(current);
+				self.current = unsafe { current.as_ref() }.next.get(self.list.version);
+				self.index += 1;
+				true
 			}
+			None => false,
 		}
-		if let Some(prev) = self.prev.get(version) {
-			let prev = unsafe { get_new_version(prev).as_mut() };
-			if let Some(prev) = prev.set_ptr(version, Some(NonNull::from(self)), |l| &mut l.next) {
-				prev.cascade_ptrs(version);
+	}
+
+	/// Moves the cursor to the previous element. Returns false if the cursor is already at the
+	/// first element.
+	pub fn move_prev(&mut self) -> bool {
+		match self.prev {
+			Some(prev) => {
+				self.current = Some(prev);
+				self.prev = self.list.prev_of(prev);
+				self.index -= 1;
+				true
 			}
+			None => false,
 		}
 	}
+
+	/// Inserts the value before the current element in a new version. The returned cursor points
+	/// at the same element as this cursor.
+	pub fn insert_before(&self, value: T) -> (PersistenLinkedList<T>, Cursor<T>) {
+		let len = self.list.len() + 1;
+		let (list, node) = self
+			.list
+			.insert_between(self.prev, self.current, Rc::new(value), len);
+		let cursor = Cursor {
+			list: list.clone(),
+			prev: Some(node),
+			current: self.current,
+			index: self.index + 1,
+		};
+		(list, cursor)
+	}
+
+	/// Inserts the value after the current element in a new version. The returned cursor points
+	/// at the same element as this cursor. If the cursor is at the end, the value is inserted at
+	/// the end.
+	pub fn insert_after(&self, value: T) -> (PersistenLinkedList<T>, Cursor<T>) {
+		let Some(current) = self.current else {
+			return self.insert_before(value);
+		};
+		let next = unsafe { current.as_ref() }.next.get(self.list.version);
+		let len = self.list.len() + 1;
+		let (list, _) = self
+			.list
+			.insert_between(Some(current), next, Rc::new(value), len);
+		let cursor = Cursor {
+			list: list.clone(),
+			prev: self.prev,
+			current: self.current,
+			index: self.index,
+		};
+		(list, cursor)
+	}
+
+	/// Removes the current element in a new version. The returned cursor points at the element
+	/// after the removed element. Returns None if the cursor is at the end.
+	pub fn remove(&self) -> Option<(PersistenLinkedList<T>, Cursor<T>)> {
+		let (list, next) = self.list.remove_node(self.prev, self.current?);
+		let cursor = Cursor {
+			list: list.clone(),
+			prev: self.prev,
+			current: next,
+			index: self.index,
+		};
+		Some((list, cursor))
+	}
 }
 
-impl<T> PersistentLinkedListPointer<T> {
-	fn new(version: usize) -> PersistentLinkedListPointer<T> {
-		PersistentLinkedListPointer {
-			original_version: version,
-			original: None,
-			new_version: None,
-			new: None,
-		}
-	}
-
-	fn get(&self, version: usize) -> Option<NonNull<PersistentLinkedListInner<T>>> {
-		assert!(version >= self.original_version);
-		match self.new_version {
-			Some(v) if v.get() <= version => self.new,
-			_ => self.original,
-		}
-	}
-
-	/// Returns true if a copy is required
-	fn update(
-		&mut self,
-		version: usize,
-		ptr: Option<NonNull<PersistentLinkedListInner<T>>>,
-	) -> bool {
-		match self.new_version {
-			Some(v) => {
-				if v.get() == version {
-					self.new = ptr;
-					false
-				} else {
-					assert!(v.get() < version);
-					true
-				}
-			}
-			None => {
-				if self.original_version == version {
-					self.original = ptr;
-				} else {
-					assert!(self.original_version < version);
-					assert!(version > 0);
-					self.new_version = NonZero::new(version);
-					self.new = ptr;
-				}
-				false
-			}
+impl<T> Clone for PersistenLinkedList<T> {
+	fn clone(&self) -> Self {
+		PersistenLinkedList {
+			handles: self.handles.clone(),
+			..self.with_ends(self.value, self.tail, self.version, self.len)
 		}
 	}
 }
 
-#[cfg(test)]
-mod test {
-	use crate::PersistenLinkedList;
+impl<T> FromIterator<T> for PersistenLinkedList<T> {
+	/// Creates a new list with a new version tree containing the items.
+	fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+		PersistenLinkedList::from_values(iter.into_iter().map(Rc::new).collect())
+	}
+}
 
-	#[test]
-	fn no_persistence_insert_begin() {
-		let mut list = PersistenLinkedList::new();
-		for i in 0..5 {
-			list = list.insert(0, i).unwrap();
-		}
-		list.crawl_debug();
-		for i in 0..5 {
-			assert_eq!(list.get(i), Some(&(4 - i)));
-		}
+impl<T: fmt::Debug> fmt::Debug for PersistenLinkedList<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_list().entries(self).finish()
 	}
-	
-	#[test]
-	fn no_persistence_insert_end() {
-		let mut list = PersistenLinkedList::new();
-		for i in 0..5 {
-			list = list.insert(i, i).unwrap();
+}
+
+/// Formats the elements of this version like a slice is debug formatted, such as `[a, b, c]`,
+/// but with the elements display formatted. The alternate form separates the elements by arrows
+/// instead, such as `a -> b -> c`.
+impl<T: fmt::Display> fmt::Display for PersistenLinkedList<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let arrows = f.alternate();
+		if !arrows {
+			f.write_str("[")?;
 		}
-		list.crawl_debug();
-		for i in 0..5 {
-			assert_eq!(list.get(i), Some(&i));
+		for (i, value) in self.iter().enumerate() {
+			if i > 0 {
+				f.write_str(if arrows { " -> " } else { ", " })?;
+			}
+			write!(f, "{value}")?;
 		}
-	}
-	
-	#[test]
-	fn no_persistence_insert_middle() {
-		let mut list = PersistenLinkedList::new().insert(0, 10).unwrap();
-		for i in 0..5 {
-			list = list.insert(1, i).unwrap();
+		if !arrows {
+			f.write_str("]")?;
 		}
-		list.crawl_debug();
-		assert_eq!(list.get(0), Some(&10));
+		Ok(())
+	}
+}
+
+/// Serializes the elements of this version as a sequence. Versions are not serialized.
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for PersistenLinkedList<T> {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(self)
+	}
+}
+
+/// Deserializes a sequence into a new list with a new version tree.
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for PersistenLinkedList<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Vec::deserialize(deserializer).map(PersistenLinkedList::from_vec)
+	}
+}
+
+impl<T: PartialEq> PartialEq for PersistenLinkedList<T> {
+	/// Compares the elements of the two versions. Handles of the same version with the same first
+	/// node are equal without traversing the list, and lists of different length are unequal
+	/// without traversing.
+	fn eq(&self, other: &Self) -> bool {
+		(self.value == other.value
+			&& self
+				.version
+				.try_cmp(&other.version)
+				.is_some_and(|o| o.is_eq()))
+			|| (self.len() == other.len() && self.iter().eq(other))
+	}
+}
+
+impl<T: Eq> Eq for PersistenLinkedList<T> {}
+
+/// Hashes the length and the elements of this version, consistent with `PartialEq`.
+impl<T: Hash> Hash for PersistenLinkedList<T> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		state.write_usize(self.len());
+		for value in self {
+			value.hash(state);
+		}
+	}
+}
+
+impl<'a, T> IntoIterator for &'a PersistenLinkedList<T> {
+	type Item = &'a T;
+
+	type IntoIter = Iter<'a, T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// Iterator over the elements of a version of a list.
+pub struct Iter<'a, T> {
+	nodes: Nodes<T>,
+	marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+	type Item = &'a T;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.nodes
+			.next()
+			.map(|node| unsafe { node.as_ref() }.value.as_ref())
+	}
+}
+
+/// A view into a specific version of a list.
+pub struct PersistentLinkedListView<'a, T> {
+	list: &'a PersistenLinkedList<T>,
+}
+
+impl<'a, T> PersistentLinkedListView<'a, T> {
+	/// Gets the element at the index in this version. Returns None if the index is out of
+	/// bounds.
+	pub fn get(&self, index: usize) -> Option<&'a T> {
+		self.list.get(index)
+	}
+
+	pub fn len(&self) -> usize {
+		self.list.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.list.is_empty()
+	}
+
+	/// Returns an iterator over the elements in this version, which can be iterated from both
+	/// ends.
+	pub fn iter(&self) -> ViewIter<'a, T> {
+		ViewIter {
+			front: self.list.value,
+			back: self.list.tail,
+			len: self.len(),
+			version: self.list.version,
+			marker: PhantomData,
+		}
+	}
+}
+
+impl<'a, T> IntoIterator for PersistentLinkedListView<'a, T> {
+	type Item = &'a T;
+
+	type IntoIter = ViewIter<'a, T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<'a, T> IntoIterator for &PersistentLinkedListView<'a, T> {
+	type Item = &'a T;
+
+	type IntoIter = ViewIter<'a, T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<T> Index<usize> for PersistentLinkedListView<'_, T> {
+	type Output = T;
+
+	fn index(&self, index: usize) -> &Self::Output {
+		match self.get(index) {
+			Some(value) => value,
+			None => panic!(
+				"Index out of bounds. Index was {} len was {}",
+				index,
+				self.len()
+			),
+		}
+	}
+}
+
+/// Double ended iterator over the elements of a version of a list. See
+/// `PersistentLinkedListView::iter`.
+pub struct ViewIter<'a, T> {
+	front: Option<NonNull<PersistentLinkedListInner<T>>>,
+	back: Option<NonNull<PersistentLinkedListInner<T>>>,
+	// The number of elements between front and back, inclusive
+	len: usize,
+	version: Version,
+	marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for ViewIter<'a, T> {
+	type Item = &'a T;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.len == 0 {
+			return None;
+		}
+		let node = unsafe { self.front?.as_ref() };
+		self.len -= 1;
+		self.front = node.next.get(self.version);
+		Some(node.value.as_ref())
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.len, Some(self.len))
+	}
+}
+
+impl<T> DoubleEndedIterator for ViewIter<'_, T> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		if self.len == 0 {
+			return None;
+		}
+		let node = unsafe { self.back?.as_ref() };
+		self.len -= 1;
+		self.back = node.prev.get(self.version);
+		Some(node.value.as_ref())
+	}
+}
+
+impl<T> ExactSizeIterator for ViewIter<'_, T> {}
+
+/// Iterator over the windows of a version of a list. See `PersistenLinkedList::windows`.
+pub struct Windows<'a, T> {
+	iter: Iter<'a, T>,
+	window: VecDeque<&'a T>,
+	size: usize,
+}
+
+impl<'a, T> Iterator for Windows<'a, T> {
+	type Item = Vec<&'a T>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.window.len() == self.size {
+			self.window.pop_front();
+		}
+		while self.window.len() < self.size {
+			self.window.push_back(self.iter.next()?);
+		}
+		Some(self.window.iter().copied().collect())
+	}
+}
+
+/// Index over a version of a list for random access in O(√n) time. See
+/// `PersistenLinkedList::skip_index`.
+pub struct SkipIndex<'a, T> {
+	list: &'a PersistenLinkedList<T>,
+	checkpoints: Vec<NonNull<PersistentLinkedListInner<T>>>,
+	stride: usize,
+	len: usize,
+}
+
+impl<'a, T> SkipIndex<'a, T> {
+	/// Returns the element at the given index, or None if the index is out of bounds. At most √n
+	/// nodes are traversed.
+	pub fn get(&self, index: usize) -> Option<&'a T> {
+		if index >= self.len {
+			return None;
+		}
+		let checkpoint = self.checkpoints[index / self.stride];
+		get_on_opt(Some(checkpoint), index % self.stride, self.list.version)
+			.map(|ptr| unsafe { &*ptr })
+	}
+
+	/// Returns the number of elements in the indexed version.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Returns true if the indexed version has no elements.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+}
+
+/// Iterator over the nodes of a version of a list.
+struct Nodes<T> {
+	next: Option<NonNull<PersistentLinkedListInner<T>>>,
+	version: Version,
+}
+
+impl<T> Iterator for Nodes<T> {
+	type Item = NonNull<PersistentLinkedListInner<T>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let ptr = self.next?;
+		self.next = unsafe { ptr.as_ref() }.next.get(self.version);
+		Some(ptr)
+	}
+}
+
+/// Allocates a chain of new nodes for the values in the given version, where the first node points
+/// back to prev. Returns the first and last node of the chain, or None if there are no values. The
+/// next pointer of the last node is None.
+#[allow(clippy::type_complexity)]
+fn alloc_chain<T>(
+	values: impl Iterator<Item = Rc<T>>,
+	version: Version,
+	prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+) -> Option<(
+	NonNull<PersistentLinkedListInner<T>>,
+	NonNull<PersistentLinkedListInner<T>>,
+)> {
+	let mut head = None;
+	let mut last = prev;
+	for value in values {
+		let node = PersistentLinkedListInner::alloc(value, version, last, None);
+		match last {
+			Some(mut last) if head.is_some() => {
+				unsafe { last.as_mut() }.next.set(version, Some(node))
+			}
+			_ => head = Some(node),
+		}
+		last = Some(node);
+	}
+	Some((head?, last?))
+}
+
+impl<T> PersistentLinkedListInner<T> {
+	fn alloc(
+		value: Rc<T>,
+		version: Version,
+		prev: Option<NonNull<PersistentLinkedListInner<T>>>,
+		next: Option<NonNull<PersistentLinkedListInner<T>>>,
+	) -> NonNull<PersistentLinkedListInner<T>> {
+		let mut ret = PersistentLinkedListInner {
+			value,
+			next: PersistentLinkedListPointer::new(),
+			prev: PersistentLinkedListPointer::new(),
+		};
+		ret.next.set(version, next);
+		ret.prev.set(version, prev);
+		util::count_alloc();
+		let b = Box::new(ret);
+		NonNull::from(Box::leak(b))
+	}
+}
+
+impl<T> PersistentLinkedListPointer<T> {
+	fn new() -> PersistentLinkedListPointer<T> {
+		PersistentLinkedListPointer {
+			tree: BTreeMap::new(),
+		}
+	}
+
+	fn get(&self, version: Version) -> Option<NonNull<PersistentLinkedListInner<T>>> {
+		self.tree
+			.range(..=version.primary)
+			.last()
+			.and_then(|(_, ptr)| *ptr)
+	}
+
+	/// Sets the pointer for the given version. The value from before the version is restored in
+	/// the secondary version such that versions which are not descendants of this version are
+	/// unaffected. This requires that no descendants of the version have been created yet.
+	fn set(&mut self, version: Version, ptr: Option<NonNull<PersistentLinkedListInner<T>>>) {
+		let old = self
+			.tree
+			.range(..version.primary)
+			.last()
+			.and_then(|(_, ptr)| *ptr);
+		self.tree.entry(version.secondary).or_insert(old);
+		self.tree.insert(version.primary, ptr);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{DiffOp, Error, PersistenLinkedList};
+
+	#[test]
+	fn no_persistence_insert_begin() {
+		let mut list = PersistenLinkedList::new();
+		for i in 0..5 {
+			list = list.insert(0, i).unwrap();
+		}
+		list.crawl_debug();
+		for i in 0..5 {
+			assert_eq!(list.get(i), Some(&(4 - i)));
+		}
+	}
+	
+	#[test]
+	fn no_persistence_insert_end() {
+		let mut list = PersistenLinkedList::new();
+		for i in 0..5 {
+			list = list.insert(i, i).unwrap();
+		}
+		list.crawl_debug();
+		for i in 0..5 {
+			assert_eq!(list.get(i), Some(&i));
+		}
+	}
+	
+	#[test]
+	fn no_persistence_insert_middle() {
+		let mut list = PersistenLinkedList::new().insert(0, 10).unwrap();
+		for i in 0..5 {
+			list = list.insert(1, i).unwrap();
+		}
+		list.crawl_debug();
+		assert_eq!(list.get(0), Some(&10));
 		for i in 0..5 {
 			assert_eq!(list.get(i + 1), Some(&(4 - i)));
 		}
@@ -279,4 +1489,1354 @@ mod test {
 			}
 		}
 	}
+
+	#[test]
+	fn persistence_branch_middle() {
+		let mut lists = vec![PersistenLinkedList::new()];
+		for i in 0..6 {
+			lists.push(lists.last().unwrap().insert(i, i).unwrap());
+		}
+		let mut branch = lists[3].insert(1, 10).unwrap();
+		branch = branch.insert(0, 11).unwrap();
+		for (len, list) in lists.iter().enumerate() {
+			for i in 0..len {
+				assert_eq!(list.get(i), Some(&i));
+			}
+			assert_eq!(list.get(len), None);
+		}
+		let expected = [11, 0, 10, 1, 2];
+		for (i, value) in expected.iter().enumerate() {
+			assert_eq!(branch.get(i), Some(value));
+		}
+		assert_eq!(branch.get(expected.len()), None);
+	}
+
+	#[test]
+	fn persistence_branch_twice() {
+		let base = PersistenLinkedList::new()
+			.insert(0, 0)
+			.unwrap()
+			.insert(1, 1)
+			.unwrap();
+		let a = base.insert(1, 2).unwrap().insert(3, 3).unwrap();
+		let b = base.insert(0, 4).unwrap().insert(2, 5).unwrap();
+		for (list, expected) in [
+			(&base, &[0, 1][..]),
+			(&a, &[0, 2, 1, 3]),
+			(&b, &[4, 0, 5, 1]),
+		] {
+			for (i, value) in expected.iter().enumerate() {
+				assert_eq!(list.get(i), Some(value));
+			}
+			assert_eq!(list.get(expected.len()), None);
+		}
+	}
+
+	fn from_slice(values: &[i32]) -> PersistenLinkedList<i32> {
+		let mut list = PersistenLinkedList::new();
+		for (i, &value) in values.iter().enumerate() {
+			list = list.insert(i, value).unwrap();
+		}
+		list
+	}
+
+	#[test]
+	fn concat() {
+		let a = from_slice(&[1, 2, 3]);
+		let b = from_slice(&[4, 5]);
+		let c = a.concat(&b);
+		assert_eq!(c.len(), a.len() + b.len());
+		for i in 0..a.len() {
+			assert_eq!(a.get(i), c.get(i));
+		}
+		for j in 0..b.len() {
+			assert_eq!(b.get(j), c.get(a.len() + j));
+		}
+		assert_eq!(a.len(), 3);
+		assert_eq!(b.len(), 2);
+		assert_eq!(a.get(3), None);
+	}
+
+	#[test]
+	fn concat_empty() {
+		let empty = PersistenLinkedList::new();
+		let list = from_slice(&[1, 2, 3]);
+		for result in [empty.concat(&list), list.concat(&empty)] {
+			assert_eq!(result.len(), list.len());
+			for i in 0..list.len() {
+				assert_eq!(result.get(i), list.get(i));
+			}
+		}
+		assert!(empty.is_empty());
+	}
+
+	#[test]
+	fn contains() {
+		let v1 = PersistenLinkedList::new().insert(0, 1).unwrap();
+		let v2 = v1.insert(1, 2).unwrap();
+		let v3 = v2.insert(0, 3).unwrap();
+		assert!(v3.contains(&1) && v3.contains(&2) && v3.contains(&3));
+		assert!(v2.contains(&1) && v2.contains(&2));
+		assert!(!v2.contains(&3));
+		assert!(!v1.contains(&2));
+		assert!(!PersistenLinkedList::new().contains(&1));
+	}
+
+	#[test]
+	fn first_last() {
+		let empty = PersistenLinkedList::new();
+		assert!(empty.is_empty());
+		assert_eq!(empty.first(), None);
+		assert_eq!(empty.last(), None);
+		let single = empty.insert(0, 1).unwrap();
+		assert!(!single.is_empty());
+		assert_eq!(single.first(), Some(&1));
+		assert_eq!(single.last(), Some(&1));
+		let list = from_slice(&[1, 2, 3]);
+		assert!(!list.is_empty());
+		assert_eq!(list.first(), Some(&1));
+		assert_eq!(list.last(), Some(&3));
+	}
+
+	#[test]
+	fn segments() {
+		let list = from_slice(&[1, 2, 3, 4, 5]);
+		let all: Vec<_> = list.iter().collect();
+		assert_eq!(all, [&1, &2, &3, &4, &5]);
+		for split in 0..=list.len() {
+			let (front, back) = list.segments(split).unwrap();
+			let front: Vec<_> = front.collect();
+			let back: Vec<_> = back.collect();
+			assert_eq!(front, all[..split]);
+			assert_eq!(back, all[split..]);
+			assert_eq!([front, back].concat(), all);
+		}
+		assert!(list.segments(list.len() + 1).is_none());
+		assert!(PersistenLinkedList::<i32>::new().segments(0).is_some());
+	}
+
+	#[test]
+	fn eq() {
+		let a = from_slice(&[1, 2, 3]);
+		let b = PersistenLinkedList::new()
+			.insert(0, 3)
+			.unwrap()
+			.insert(0, 1)
+			.unwrap()
+			.insert(1, 2)
+			.unwrap();
+		assert!(a == a);
+		assert!(a == b);
+		let c = from_slice(&[1, 2, 4]);
+		assert!(a != c);
+		let d = from_slice(&[1, 2]);
+		assert!(a != d);
+		assert!(d != a);
+		assert!(PersistenLinkedList::<i32>::new() == PersistenLinkedList::new());
+	}
+
+	#[test]
+	fn position() {
+		let list = from_slice(&[1, 2, 3, 2]);
+		assert_eq!(list.position(&1), Some(0));
+		assert_eq!(list.position(&2), Some(1));
+		assert_eq!(list.position(&3), Some(2));
+		assert_eq!(list.position(&4), None);
+		assert!(!list.contains(&4));
+		let a = list.insert(1, 5).unwrap();
+		let b = list.insert(1, 6).unwrap();
+		assert_eq!(a.position(&5), Some(1));
+		assert_eq!(a.position(&6), None);
+		assert_eq!(b.position(&6), Some(1));
+		assert_eq!(b.position(&5), None);
+		assert_eq!(list.position(&5), None);
+		assert_eq!(PersistenLinkedList::new().position(&1), None);
+	}
+
+	#[test]
+	fn debug() {
+		let list = from_slice(&[1, 2, 3]);
+		let branch = list.insert(1, 4).unwrap();
+		assert_eq!(format!("{:?}", list), "[1, 2, 3]");
+		assert_eq!(format!("{:?}", branch), "[1, 4, 2, 3]");
+		assert_eq!(format!("{:?}", PersistenLinkedList::<i32>::new()), "[]");
+	}
+
+	#[test]
+	fn get_range() {
+		let list = from_slice(&(0..10).collect::<Vec<_>>());
+		assert_eq!(list.get_range(2, 5).collect::<Vec<_>>(), [&2, &3, &4]);
+		assert_eq!(list.get_range(8, 20).collect::<Vec<_>>(), [&8, &9]);
+		assert_eq!(list.get_range(5, 5).count(), 0);
+		assert_eq!(list.get_range(5, 2).count(), 0);
+		assert_eq!(list.get_range(10, 12).count(), 0);
+		assert_eq!(list.get_range(20, 30).count(), 0);
+	}
+
+	#[test]
+	fn remove() {
+		let list = from_slice(&[1, 2, 3, 4]);
+		let a = list.remove(0).unwrap();
+		let b = list.remove(2).unwrap();
+		let c = list.remove(3).unwrap();
+		assert!(list.remove(4).is_none());
+		assert_eq!(format!("{:?}", list), "[1, 2, 3, 4]");
+		assert_eq!(format!("{:?}", a), "[2, 3, 4]");
+		assert_eq!(format!("{:?}", b), "[1, 2, 4]");
+		assert_eq!(format!("{:?}", c), "[1, 2, 3]");
+		let single = from_slice(&[1]).remove(0).unwrap();
+		assert!(single.is_empty());
+		assert!(single.remove(0).is_none());
+	}
+
+	#[test]
+	fn cursor() {
+		let list = from_slice(&[0, 1]);
+		let mut cursor = list.cursor_at(1).unwrap();
+		let mut model = vec![0, 1];
+		let mut versions = Vec::new();
+		for i in 2..10_000 {
+			let (new_list, new_cursor) = cursor.insert_before(i);
+			model.insert(new_cursor.index() - 1, i);
+			cursor = new_cursor;
+			if i % 1000 == 0 {
+				versions.push((new_list, model.clone()));
+			}
+		}
+		assert_eq!(cursor.current(), Some(&1));
+		assert_eq!(cursor.index(), model.len() - 1);
+		for (list, model) in versions {
+			assert!(list.iter().eq(&model));
+		}
+		assert!(cursor.list().iter().eq(&model));
+		assert_eq!(format!("{:?}", list), "[0, 1]");
+	}
+
+	#[test]
+	fn cursor_edit() {
+		let list = from_slice(&[1, 2, 3]);
+		let mut cursor = list.cursor_at(0).unwrap();
+		assert!(!cursor.move_prev());
+		assert!(cursor.move_next());
+		assert_eq!(cursor.current(), Some(&2));
+		let (after, after_cursor) = cursor.insert_after(4);
+		assert_eq!(format!("{:?}", after), "[1, 2, 4, 3]");
+		assert_eq!(after_cursor.current(), Some(&2));
+		let (removed, mut removed_cursor) = after_cursor.remove().unwrap();
+		assert_eq!(format!("{:?}", removed), "[1, 4, 3]");
+		assert_eq!(removed_cursor.current(), Some(&4));
+		assert!(removed_cursor.move_prev());
+		assert_eq!(removed_cursor.current(), Some(&1));
+		assert!(removed_cursor.move_next() && removed_cursor.move_next());
+		assert!(removed_cursor.move_next());
+		assert_eq!(removed_cursor.current(), None);
+		assert!(!removed_cursor.move_next());
+		assert!(removed_cursor.remove().is_none());
+		let (end, _) = removed_cursor.insert_after(5);
+		assert_eq!(format!("{:?}", end), "[1, 4, 3, 5]");
+		assert!(cursor.move_next() && cursor.move_next());
+		let (end, _) = cursor.insert_before(6);
+		assert_eq!(format!("{:?}", end), "[1, 2, 3, 6]");
+		assert_eq!(format!("{:?}", list), "[1, 2, 3]");
+	}
+
+	#[test]
+	fn map() {
+		let list = from_slice(&[1, 2, 3]);
+		let mapped = list.map(|value| format!("{}", value * 2));
+		assert_eq!(mapped.len(), list.len());
+		assert_eq!(format!("{:?}", mapped), r#"["2", "4", "6"]"#);
+		let inserted = list.insert(0, 0).unwrap().remove(2).unwrap();
+		assert_eq!(format!("{:?}", inserted), "[0, 1, 3]");
+		assert_eq!(format!("{:?}", mapped), r#"["2", "4", "6"]"#);
+		let mapped = mapped.insert(1, "3".to_string()).unwrap();
+		assert_eq!(format!("{:?}", mapped), r#"["2", "3", "4", "6"]"#);
+		assert!(PersistenLinkedList::<i32>::new().map(|v| *v).is_empty());
+	}
+
+	#[test]
+	fn reverse() {
+		let list = from_slice(&[1, 2, 3, 4]);
+		let reversed = list.reverse();
+		assert_eq!(format!("{:?}", reversed), "[4, 3, 2, 1]");
+		assert!(list.reverse().reverse() == list);
+		assert_eq!(format!("{:?}", list), "[1, 2, 3, 4]");
+		let inserted = reversed.insert(4, 0).unwrap();
+		assert_eq!(format!("{:?}", inserted), "[4, 3, 2, 1, 0]");
+		assert_eq!(format!("{:?}", list), "[1, 2, 3, 4]");
+		assert!(PersistenLinkedList::<i32>::new().reverse().is_empty());
+	}
+
+	#[test]
+	fn replace() {
+		let list = from_slice(&[1, 2, 3]);
+		for index in 0..3 {
+			let replaced = list.replace(index, 10).unwrap();
+			assert_eq!(replaced.get(index), Some(&10));
+			assert_eq!(list.get(index), Some(&(index as i32 + 1)));
+			assert_eq!(replaced.len(), 3);
+		}
+		assert!(list.replace(3, 10).is_none());
+		let replaced = list.replace(1, 10).unwrap().replace(1, 20).unwrap();
+		assert_eq!(format!("{:?}", replaced), "[1, 20, 3]");
+		assert_eq!(format!("{:?}", list), "[1, 2, 3]");
+	}
+
+	#[test]
+	fn pop_stack() {
+		let mut versions = vec![PersistenLinkedList::new()];
+		for i in 0..5 {
+			versions.push(versions.last().unwrap().insert(0, i).unwrap());
+		}
+		let mut list = versions.last().unwrap().clone();
+		for i in (0..5).rev() {
+			let (popped, value) = list.pop_front().unwrap();
+			assert_eq!(*value, i);
+			list = popped;
+		}
+		assert!(list.is_empty());
+		assert_eq!(list.get(0), None);
+		assert!(list.pop_front().is_none());
+		assert!(list.pop_back().is_none());
+		for (len, list) in versions.iter().enumerate() {
+			assert_eq!(list.len(), len);
+			assert_eq!(list.first(), len.checked_sub(1).map(|i| i as i32).as_ref());
+		}
+	}
+
+	#[test]
+	fn pop_queue() {
+		let mut versions = vec![PersistenLinkedList::new()];
+		for i in 0..5 {
+			versions.push(versions.last().unwrap().insert(0, i).unwrap());
+		}
+		let mut list = versions.last().unwrap().clone();
+		for i in 0..5 {
+			let (popped, value) = list.pop_back().unwrap();
+			assert_eq!(*value, i);
+			list = popped;
+		}
+		assert!(list.is_empty());
+		assert_eq!(list.get(0), None);
+		for (len, list) in versions.iter().enumerate() {
+			assert_eq!(list.len(), len);
+			assert_eq!(list.last(), (len > 0).then_some(&0));
+		}
+	}
+
+	#[test]
+	fn try_insert_remove() {
+		let empty = PersistenLinkedList::new();
+		assert_eq!(
+			empty.try_insert(1, 0).err(),
+			Some(Error::IndexOutOfBounds { index: 1, len: 0 })
+		);
+		assert_eq!(
+			empty.try_remove(0).err(),
+			Some(Error::IndexOutOfBounds { index: 0, len: 0 })
+		);
+		let list = from_slice(&[1, 2, 3]);
+		assert_eq!(
+			list.try_insert(4, 0).err(),
+			Some(Error::IndexOutOfBounds { index: 4, len: 3 })
+		);
+		assert_eq!(
+			list.try_remove(3).err(),
+			Some(Error::IndexOutOfBounds { index: 3, len: 3 })
+		);
+		assert_eq!(
+			Error::IndexOutOfBounds { index: 4, len: 3 }.to_string(),
+			"Index out of bounds. Index was 4 len was 3"
+		);
+		let inserted = list.try_insert(3, 4).unwrap();
+		assert_eq!(format!("{:?}", inserted), "[1, 2, 3, 4]");
+		let removed = inserted.try_remove(0).unwrap();
+		assert_eq!(format!("{:?}", removed), "[2, 3, 4]");
+	}
+
+	#[test]
+	fn pop_front_history() {
+		let list = from_slice(&[1, 2, 3]);
+		let (a, one) = list.pop_front().unwrap();
+		let (b, two) = a.pop_front().unwrap();
+		let (c, three) = b.pop_front().unwrap();
+		assert_eq!((*one, *two, *three), (1, 2, 3));
+		assert!(c.pop_front().is_none());
+		assert_eq!(format!("{:?}", list), "[1, 2, 3]");
+		assert_eq!(format!("{:?}", a), "[2, 3]");
+		assert_eq!(format!("{:?}", b), "[3]");
+		assert_eq!(format!("{:?}", c), "[]");
+	}
+
+	#[test]
+	fn len_branches() {
+		let base = from_slice(&[1, 2, 3]);
+		let a = base.insert(0, 0).unwrap().insert(0, 0).unwrap();
+		let b = base.remove(0).unwrap();
+		let c = a.concat(&b);
+		assert_eq!(base.len(), 3);
+		assert_eq!(a.len(), 5);
+		assert_eq!(b.len(), 2);
+		assert_eq!(c.len(), 7);
+		assert_eq!(base.reverse().len(), 3);
+		assert_eq!(PersistenLinkedList::<i32>::new().len(), 0);
+		for list in [&base, &a, &b, &c] {
+			assert_eq!(list.len(), list.iter().count());
+		}
+	}
+
+	#[test]
+	fn reversed() {
+		let list = from_slice(&[1, 2, 3, 4]);
+		let reversed = list.reversed();
+		assert_eq!(format!("{:?}", reversed), "[4, 3, 2, 1]");
+		let mut expected: Vec<_> = list.iter().collect();
+		expected.reverse();
+		assert_eq!(reversed.iter().collect::<Vec<_>>(), expected);
+		assert_eq!(format!("{:?}", list), "[1, 2, 3, 4]");
+		assert!(reversed.reversed() == list);
+		let inserted = reversed.insert(1, 5).unwrap().remove(4).unwrap();
+		assert_eq!(format!("{:?}", inserted), "[4, 5, 3, 2]");
+		assert_eq!(format!("{:?}", reversed), "[4, 3, 2, 1]");
+		assert_eq!(format!("{:?}", list), "[1, 2, 3, 4]");
+		assert!(PersistenLinkedList::<i32>::new().reversed().is_empty());
+	}
+
+	#[test]
+	fn cursor_scan() {
+		let list = from_slice(&(0..1000).collect::<Vec<_>>());
+		let mut cursor = list.cursor();
+		let mut values = Vec::new();
+		while let Some(&value) = cursor.current() {
+			assert_eq!(list.get(cursor.index()), Some(&value));
+			values.push(value);
+			cursor.move_next();
+		}
+		assert_eq!(values, (0..1000).collect::<Vec<_>>());
+		while cursor.move_prev() {
+			assert_eq!(cursor.current(), values.pop().as_ref());
+		}
+		assert_eq!(cursor.index(), 0);
+		let (inserted, _) = cursor.insert_before(-1);
+		assert_eq!(inserted.first(), Some(&-1));
+		assert_eq!(list.first(), Some(&0));
+		assert_eq!(PersistenLinkedList::<i32>::new().cursor().current(), None);
+	}
+
+	#[test]
+	fn last_branches() {
+		let base = from_slice(&[1, 2]);
+		let a = base.insert(2, 3).unwrap();
+		let b = base.insert(2, 4).unwrap();
+		let c = b.pop_back().unwrap().0.pop_back().unwrap().0;
+		let d = c.pop_back().unwrap().0;
+		assert_eq!(base.last(), Some(&2));
+		assert_eq!(a.last(), Some(&3));
+		assert_eq!(b.last(), Some(&4));
+		assert_eq!(c.last(), Some(&1));
+		assert_eq!(c.first(), Some(&1));
+		assert!(!c.is_empty());
+		assert_eq!(d.last(), None);
+		assert_eq!(d.first(), None);
+		assert!(d.is_empty());
+		let e = d.insert(0, 5).unwrap();
+		assert_eq!((e.first(), e.last()), (Some(&5), Some(&5)));
+		let f = a.concat(&b).reversed().remove(0).unwrap();
+		assert_eq!(format!("{:?}", f), "[2, 1, 3, 2, 1]");
+		assert_eq!((f.first(), f.last()), (Some(&2), Some(&1)));
+		for list in [&base, &a, &b, &c, &d, &e, &f] {
+			assert_eq!(list.last(), list.iter().last());
+		}
+	}
+
+	#[test]
+	fn extend() {
+		let list = from_slice(&[9]);
+		let extended = list.extend(0..3);
+		assert_eq!(format!("{:?}", extended), "[9, 0, 1, 2]");
+		assert_eq!(format!("{:?}", list), "[9]");
+		assert_eq!(extended.len(), 4);
+		assert_eq!(extended.last(), Some(&2));
+		let empty = PersistenLinkedList::new().extend(0..3);
+		assert_eq!(format!("{:?}", empty), "[0, 1, 2]");
+		assert!(list.extend(0..0) == list);
+	}
+
+	#[test]
+	fn from_iter() {
+		let list: PersistenLinkedList<_> = (0..5).collect();
+		assert_eq!(format!("{:?}", list), "[0, 1, 2, 3, 4]");
+		assert_eq!(list.len(), 5);
+		assert_eq!(list.last(), Some(&4));
+		let list: PersistenLinkedList<i32> = std::iter::empty().collect();
+		assert!(list.is_empty());
+	}
+
+	#[test]
+	fn to_vec() {
+		let list = from_slice(&[1, 2, 3]);
+		assert_eq!(list.to_vec(), [1, 2, 3]);
+		assert_eq!(list.to_vec(), list.reverse().reverse().to_vec());
+		assert_eq!(list.to_vec_ref(), [&1, &2, &3]);
+		let mut vec = list.to_vec();
+		vec[0] = 10;
+		vec.push(4);
+		assert_eq!(list.to_vec(), [1, 2, 3]);
+		assert!(PersistenLinkedList::<i32>::new().to_vec().is_empty());
+	}
+
+	#[test]
+	fn to_vec_snapshots() {
+		let mut snapshots = vec![(PersistenLinkedList::new(), Vec::new())];
+		for _ in 0..200 {
+			let (list, model) = &snapshots[fastrand::usize(..snapshots.len())];
+			let mut model = model.clone();
+			let list = if model.is_empty() || fastrand::bool() {
+				let index = fastrand::usize(..=model.len());
+				let value = fastrand::u32(..);
+				model.insert(index, value);
+				list.insert(index, value).unwrap()
+			} else {
+				let index = fastrand::usize(..model.len());
+				model.remove(index);
+				list.remove(index).unwrap()
+			};
+			snapshots.push((list, model));
+		}
+		for (list, model) in &snapshots {
+			assert_eq!(&list.to_vec(), model);
+			let rc: Vec<_> = list.to_vec_rc().iter().map(|value| **value).collect();
+			assert_eq!(&rc, model);
+		}
+	}
+
+	#[test]
+	fn from_vec() {
+		for v in [vec![], vec![7], (0..50).collect::<Vec<i32>>()] {
+			let list = PersistenLinkedList::from_vec(v.clone());
+			assert_eq!(list.to_vec(), v);
+			assert_eq!(list.len(), v.len());
+			for (i, value) in v.iter().enumerate() {
+				assert_eq!(list.get(i), Some(value));
+			}
+			assert_eq!(list.get(v.len()), None);
+			// Lists from separate vecs do not share any history.
+			let other = PersistenLinkedList::from_vec(v.clone()).extend([1]);
+			assert_eq!(list.to_vec(), v);
+			assert_eq!(other.len(), v.len() + 1);
+		}
+	}
+
+	#[test]
+	fn swap() {
+		let list = from_slice(&[0, 1, 2, 3, 4]);
+		let swapped = list.swap(1, 3).unwrap();
+		assert_eq!(swapped.to_vec(), [0, 3, 2, 1, 4]);
+		assert_eq!(list.to_vec(), [0, 1, 2, 3, 4]);
+		assert_eq!(list.swap(4, 0).unwrap().to_vec(), [4, 1, 2, 3, 0]);
+		assert_eq!(list.swap(2, 3).unwrap().to_vec(), [0, 1, 3, 2, 4]);
+		assert_eq!(list.swap(0, 1).unwrap().to_vec(), [1, 0, 2, 3, 4]);
+		assert_eq!(list.swap(3, 4).unwrap().last(), Some(&3));
+		assert_eq!(list.swap(0, 4).unwrap().first(), Some(&4));
+		let same = list.swap(2, 2).unwrap();
+		assert!(same.version() != list.version());
+		assert_eq!(same.to_vec(), list.to_vec());
+		assert_eq!(same.len(), list.len());
+		assert!(list.swap(0, 5).is_none());
+		assert!(list.swap(5, 5).is_none());
+		let reswapped = swapped.swap(3, 1).unwrap();
+		assert_eq!(reswapped.to_vec(), [0, 1, 2, 3, 4]);
+		assert_eq!(swapped.to_vec(), [0, 3, 2, 1, 4]);
+		assert_eq!(list.to_vec(), [0, 1, 2, 3, 4]);
+		assert_eq!(reswapped.len(), 5);
+	}
+
+	#[test]
+	fn swap_random() {
+		let mut snapshots = vec![(
+			from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]),
+			vec![0, 1, 2, 3, 4, 5, 6, 7],
+		)];
+		for _ in 0..200 {
+			let (list, model) = &snapshots[fastrand::usize(..snapshots.len())];
+			let (i, j) = (
+				fastrand::usize(..model.len()),
+				fastrand::usize(..model.len()),
+			);
+			let mut model = model.clone();
+			model.swap(i, j);
+			let list = list.swap(i, j).unwrap();
+			snapshots.push((list, model));
+		}
+		for (list, model) in &snapshots {
+			assert_eq!(&list.to_vec(), model);
+			assert_eq!(list.last(), model.last());
+		}
+	}
+
+	#[test]
+	fn get_mut() {
+		let list = from_slice(&[1, 2, 3]);
+		let mut inserted = list.insert(1, 10).unwrap();
+		let version = inserted.version();
+		*inserted.get_mut(1).unwrap() += 5;
+		assert!(inserted.version() == version);
+		assert_eq!(inserted.to_vec(), [1, 15, 2, 3]);
+		assert_eq!(list.to_vec(), [1, 2, 3]);
+		// The value at index 0 is shared with the earlier version, so it is copied into a new
+		// version
+		*inserted.get_mut(0).unwrap() = 0;
+		assert!(inserted.version() != version);
+		assert_eq!(inserted.to_vec(), [0, 15, 2, 3]);
+		assert_eq!(list.to_vec(), [1, 2, 3]);
+		assert!(inserted.get_mut(4).is_none());
+	}
+
+	#[test]
+	fn get_mut_shared() {
+		let mut list = from_slice(&[1, 2, 3]).insert(0, 0).unwrap();
+		let clone = list.clone();
+		*list.get_mut(0).unwrap() = 5;
+		assert_eq!(list.to_vec(), [5, 1, 2, 3]);
+		assert_eq!(clone.to_vec(), [0, 1, 2, 3]);
+		let mut list = from_slice(&[1, 2, 3]).insert(0, 0).unwrap();
+		let child = list.insert(4, 4).unwrap();
+		*list.get_mut(0).unwrap() = 5;
+		assert_eq!(list.to_vec(), [5, 1, 2, 3]);
+		assert_eq!(child.to_vec(), [0, 1, 2, 3, 4]);
+		let mut list = from_slice(&[1, 2, 3]).insert(0, 0).unwrap();
+		let values = list.to_vec_rc();
+		*list.get_mut(0).unwrap() = 5;
+		assert_eq!(list.to_vec(), [5, 1, 2, 3]);
+		assert_eq!(*values[0], 0);
+	}
+
+	#[test]
+	fn retain() {
+		let list = from_slice(&[0, 1, 2, 3, 4, 5]);
+		let all = list.retain(|_| true);
+		assert!(all == list);
+		assert!(all.version() != list.version());
+		let none = list.retain(|_| false);
+		assert!(none.is_empty());
+		assert_eq!(none.len(), 0);
+		assert_eq!(none.last(), None);
+		let even = list.retain(|value| value % 2 == 0);
+		assert_eq!(even.to_vec(), [0, 2, 4]);
+		assert_eq!(even.len(), 3);
+		assert_eq!(even.last(), Some(&4));
+		let odd = list.retain(|value| value % 2 == 1);
+		assert_eq!(odd.to_vec(), [1, 3, 5]);
+		assert_eq!(odd.first(), Some(&1));
+		assert!(even.version() != list.version());
+		assert_eq!(list.to_vec(), [0, 1, 2, 3, 4, 5]);
+		assert_eq!(list.len(), 6);
+	}
+
+	#[test]
+	fn remove_all() {
+		let list = from_slice(&[1, 2, 1, 3, 1, 2]);
+		let none = list.remove_all(&4);
+		assert_eq!(none.to_vec(), [1, 2, 1, 3, 1, 2]);
+		assert!(none.version() != list.version());
+		let once = list.remove_all(&3);
+		assert_eq!(once.to_vec(), [1, 2, 1, 1, 2]);
+		assert_eq!(once.len(), 5);
+		let many = list.remove_all(&1);
+		assert_eq!(many.to_vec(), [2, 3, 2]);
+		assert_eq!(many.first(), Some(&2));
+		assert_eq!(many.last(), Some(&2));
+		let twice = many.remove_all(&2);
+		assert_eq!(twice.to_vec(), [3]);
+		assert!(twice.remove_all(&3).is_empty());
+		assert_eq!(list.to_vec(), [1, 2, 1, 3, 1, 2]);
+		assert_eq!(list.len(), 6);
+	}
+
+	#[test]
+	fn retain_then_insert() {
+		let list = from_slice(&[0, 1, 2, 3, 4, 5, 6]);
+		let retained = list.retain(|value| value % 3 != 0);
+		assert_eq!(retained.to_vec(), [1, 2, 4, 5]);
+		let old = list.insert(3, 10).unwrap();
+		let new = retained.insert(2, 10).unwrap().insert(4, 11).unwrap();
+		assert_eq!(old.to_vec(), [0, 1, 2, 10, 3, 4, 5, 6]);
+		assert_eq!(new.to_vec(), [1, 2, 10, 4, 11, 5]);
+		let new_retained = new.retain(|value| *value < 10);
+		let old_retained = old.retain(|value| *value >= 3);
+		assert_eq!(new_retained.to_vec(), [1, 2, 4, 5]);
+		assert_eq!(old_retained.to_vec(), [10, 3, 4, 5, 6]);
+		assert_eq!(old_retained.first(), Some(&10));
+		assert_eq!(
+			old_retained.insert(5, 7).unwrap().to_vec(),
+			[10, 3, 4, 5, 6, 7]
+		);
+		assert_eq!(retained.to_vec(), [1, 2, 4, 5]);
+		assert_eq!(retained.pop_back().unwrap().0.to_vec(), [1, 2, 4]);
+		assert_eq!(list.to_vec(), [0, 1, 2, 3, 4, 5, 6]);
+		assert_eq!(old.to_vec(), [0, 1, 2, 10, 3, 4, 5, 6]);
+		assert_eq!(new.to_vec(), [1, 2, 10, 4, 11, 5]);
+	}
+
+	#[test]
+	fn find() {
+		let list = from_slice(&[10, 20, 30]);
+		assert_eq!(list.find(|&x| x == 20), Some(1));
+		assert_eq!(list.find(|&x| x == 99), None);
+		let mut calls = 0;
+		assert_eq!(
+			list.find(|&x| {
+				calls += 1;
+				x >= 10
+			}),
+			Some(0)
+		);
+		assert_eq!(calls, 1);
+		let removed = list.remove(0).unwrap();
+		assert_eq!(removed.find(|&x| x == 20), Some(0));
+		assert_eq!(list.find(|&x| x == 20), Some(1));
+	}
+
+	#[cfg(feature = "alloc-stats")]
+	#[test]
+	fn insert_front_allocations() {
+		let n = 1000;
+		let mut list = PersistenLinkedList::new();
+		crate::reset_alloc_count();
+		for i in 0..n {
+			list = list.insert(0, i).unwrap();
+		}
+		// One list node and the two version nodes of the new version, and a super node every 32
+		// versions.
+		let count = crate::alloc_count();
+		assert!(count >= 3 * n);
+		assert!(count <= 4 * n);
+	}
+
+	#[test]
+	fn partition() {
+		let list = from_slice(&[5, 2, 8, 1, 9, 4]);
+		let (small, large) = list.partition(|&value| value < 5);
+		assert_eq!(small.to_vec(), [2, 1, 4]);
+		assert_eq!(large.to_vec(), [5, 8, 9]);
+		assert_eq!(small.len() + large.len(), list.len());
+		let mut all = small.concat(&large).to_vec();
+		all.sort();
+		assert_eq!(all, [1, 2, 4, 5, 8, 9]);
+		assert_eq!(list.to_vec(), [5, 2, 8, 1, 9, 4]);
+		let (all, none) = list.partition(|_| true);
+		assert!(all == list);
+		assert!(none.is_empty());
+		let (even, odd) = small
+			.insert(0, 6)
+			.unwrap()
+			.partition(|value| value % 2 == 0);
+		assert_eq!(even.to_vec(), [6, 2, 4]);
+		assert_eq!(odd.last(), Some(&1));
+		assert_eq!(small.to_vec(), [2, 1, 4]);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn serde() {
+		let list = from_slice(&[1, 2, 3]);
+		let old = list.insert(0, 0).unwrap();
+		let list = old.remove(2).unwrap();
+		let json = serde_json::to_string(&list).unwrap();
+		assert_eq!(json, "[0,1,3]");
+		let deserialized: PersistenLinkedList<i32> = serde_json::from_str(&json).unwrap();
+		assert_eq!(deserialized.to_vec(), list.to_vec());
+		assert_eq!(serde_json::to_string(&old).unwrap(), "[0,1,2,3]");
+		let empty: PersistenLinkedList<i32> = serde_json::from_str("[]").unwrap();
+		assert!(empty.is_empty());
+	}
+
+	#[test]
+	fn sorted_insert() {
+		let mut list = PersistenLinkedList::new();
+		let mut values = Vec::new();
+		for _ in 0..100 {
+			let value = fastrand::i32(..50);
+			list = list.sorted_insert(value);
+			values.push(value);
+		}
+		assert!(list.is_sorted());
+		assert_eq!(list.len(), 100);
+		for value in &values {
+			assert!(list.position(value).is_some());
+		}
+		values.sort();
+		assert_eq!(list.to_vec(), values);
+		assert!(!from_slice(&[1, 3, 2]).is_sorted());
+		assert!(PersistenLinkedList::<i32>::new().is_sorted());
+		let list = from_slice(&[1, 3]);
+		assert_eq!(list.sorted_insert(2).to_vec(), [1, 2, 3]);
+		assert_eq!(list.sorted_insert(3).to_vec(), [1, 3, 3]);
+		assert_eq!(list.sorted_insert(0).first(), Some(&0));
+		assert_eq!(list.to_vec(), [1, 3]);
+	}
+
+	proptest::proptest! {
+		#[test]
+		fn sort_proptest(values in proptest::collection::vec((-20i32..20, 0..100u8), 0..100)) {
+			// Only the first component is compared, so the second shows whether the sort is stable
+			#[derive(Clone, Debug)]
+			struct Key((i32, u8));
+			impl PartialEq for Key {
+				fn eq(&self, other: &Self) -> bool {
+					self.0 .0 == other.0 .0
+				}
+			}
+			impl Eq for Key {}
+			impl PartialOrd for Key {
+				fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+					Some(self.cmp(other))
+				}
+			}
+			impl Ord for Key {
+				fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+					self.0 .0.cmp(&other.0 .0)
+				}
+			}
+			let list = PersistenLinkedList::from_vec(values.iter().cloned().map(Key).collect());
+			let sorted = list.sort();
+			let mut expected: Vec<_> = values.iter().cloned().map(Key).collect();
+			expected.sort();
+			let pairs = |keys: &mut dyn Iterator<Item = &Key>| keys.map(|key| key.0).collect::<Vec<_>>();
+			proptest::prop_assert_eq!(pairs(&mut sorted.iter()), pairs(&mut expected.iter()));
+			proptest::prop_assert!(sorted.is_sorted());
+			proptest::prop_assert_eq!(sorted.len(), values.len());
+			proptest::prop_assert_eq!(pairs(&mut list.iter()), values);
+		}
+	}
+
+	#[test]
+	fn sort() {
+		let list = from_slice(&[3, 1, 2]);
+		let inserted = list.sort().insert(0, 5).unwrap();
+		assert_eq!(inserted.to_vec(), [5, 1, 2, 3]);
+		assert_eq!(inserted.sort().to_vec(), [1, 2, 3, 5]);
+		assert_eq!(list.to_vec(), [3, 1, 2]);
+	}
+
+	#[test]
+	fn get_back() {
+		let mut snapshots = vec![from_slice(&[0, 1, 2])];
+		for i in 0..100 {
+			let list = &snapshots[fastrand::usize(..snapshots.len())];
+			let list = if list.is_empty() || fastrand::bool() {
+				list.insert(fastrand::usize(..=list.len()), i).unwrap()
+			} else {
+				list.remove(fastrand::usize(..list.len())).unwrap()
+			};
+			snapshots.push(list);
+		}
+		for list in &snapshots {
+			let len = list.len();
+			for k in 0..len {
+				assert_eq!(list.get_back(k), list.get(len - 1 - k));
+			}
+			assert_eq!(list.get_back(len), None);
+			assert_eq!(list.get_back(0), list.last());
+		}
+	}
+
+	#[test]
+	fn windows() {
+		let list = from_slice(&[0, 1, 2, 3, 4]);
+		assert_eq!(list.windows(3).count(), list.len() - 2);
+		let windows: Vec<_> = list.windows(3).collect();
+		assert_eq!(windows, [[&0, &1, &2], [&1, &2, &3], [&2, &3, &4]]);
+		assert_eq!(list.windows(5).count(), 1);
+		assert_eq!(list.windows(6).count(), 0);
+		assert_eq!(list.windows(1).count(), 5);
+		let removed = list.remove(2).unwrap();
+		let windows: Vec<_> = removed.windows(2).collect();
+		assert_eq!(windows, [[&0, &1], [&1, &3], [&3, &4]]);
+		assert_eq!(PersistenLinkedList::<i32>::new().windows(1).count(), 0);
+	}
+
+	#[test]
+	#[should_panic]
+	fn windows_zero() {
+		from_slice(&[0, 1]).windows(0);
+	}
+
+	#[test]
+	fn extend_at() {
+		let list = from_slice(&[0, 1, 2]);
+		let middle = list.extend_at(1, [10, 11]).unwrap();
+		assert_eq!(middle.to_vec(), [0, 10, 11, 1, 2]);
+		assert_eq!(middle.len(), 5);
+		assert_eq!(middle.get_back(3), Some(&10));
+		let front = list.extend_at(0, [10, 11]).unwrap();
+		assert_eq!(front.to_vec(), [10, 11, 0, 1, 2]);
+		assert_eq!(front.first(), Some(&10));
+		let end = list.extend_at(3, [10, 11]).unwrap();
+		assert_eq!(end.to_vec(), [0, 1, 2, 10, 11]);
+		assert_eq!(end.last(), Some(&11));
+		let empty = list.extend_at(1, []).unwrap();
+		assert!(empty == list);
+		assert!(empty.version() != list.version());
+		assert!(list.extend_at(4, [10]).is_none());
+		assert_eq!(
+			PersistenLinkedList::new()
+				.extend_at(0, [1, 2])
+				.unwrap()
+				.to_vec(),
+			[1, 2]
+		);
+		assert_eq!(
+			middle.extend_at(5, [3]).unwrap().to_vec(),
+			[0, 10, 11, 1, 2, 3]
+		);
+		assert_eq!(list.to_vec(), [0, 1, 2]);
+		assert_eq!(middle.to_vec(), [0, 10, 11, 1, 2]);
+	}
+
+	#[test]
+	fn prepend() {
+		let list = from_slice(&[1, 2]);
+		let prepended = list.prepend(0);
+		assert_eq!(prepended.get(0), Some(&0));
+		assert_eq!(prepended.get(1), list.get(0));
+		assert_eq!(prepended.to_vec(), [0, 1, 2]);
+		assert_eq!(prepended.len(), 3);
+		assert_eq!(list.to_vec(), [1, 2]);
+		let empty = PersistenLinkedList::new().prepend(5);
+		assert_eq!(empty.to_vec(), [5]);
+		assert_eq!(empty.last(), Some(&5));
+		let mut list = PersistenLinkedList::new();
+		for i in 0..1000 {
+			list = list.prepend(i);
+		}
+		assert_eq!(list.to_vec(), (0..1000).rev().collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn splice() {
+		let list = from_slice(&[0, 1, 2, 3, 4]);
+		let spliced = list.splice(1..3, [10, 11, 12]).unwrap();
+		assert_eq!(spliced.to_vec(), [0, 10, 11, 12, 3, 4]);
+		assert_eq!(spliced.len(), 6);
+		assert_eq!(list.splice(0..5, [7]).unwrap().to_vec(), [7]);
+		assert!(list.splice(0..5, []).unwrap().to_vec().is_empty());
+		assert!(list.splice(0..5, []).unwrap().is_empty());
+		assert_eq!(list.splice(2..2, [9]).unwrap().to_vec(), [0, 1, 9, 2, 3, 4]);
+		assert_eq!(list.splice(3..5, [9]).unwrap().to_vec(), [0, 1, 2, 9]);
+		assert_eq!(list.splice(3..5, [9]).unwrap().last(), Some(&9));
+		assert_eq!(list.splice(0..2, []).unwrap().first(), Some(&2));
+		assert_eq!(list.splice(5..5, [5]).unwrap().to_vec(), [0, 1, 2, 3, 4, 5]);
+		assert_eq!(list.splice(1..4, []).unwrap().get_back(0), Some(&4));
+		assert!(list.splice(4..6, []).is_none());
+		#[allow(clippy::reversed_empty_ranges)]
+		let reversed = list.splice(3..2, []);
+		assert!(reversed.is_none());
+		assert_eq!(list.to_vec(), [0, 1, 2, 3, 4]);
+		assert_eq!(spliced.to_vec(), [0, 10, 11, 12, 3, 4]);
+	}
+
+	#[test]
+	fn splice_random() {
+		let mut snapshots = vec![(PersistenLinkedList::new(), Vec::new())];
+		for i in 0..200 {
+			let (list, model) = &snapshots[fastrand::usize(..snapshots.len())];
+			let start = fastrand::usize(..=model.len());
+			let end = fastrand::usize(start..=model.len());
+			let replacement: Vec<_> = (0..fastrand::usize(..4)).map(|j| i * 4 + j).collect();
+			let mut model = model.clone();
+			model.splice(start..end, replacement.iter().copied());
+			let list = list.splice(start..end, replacement).unwrap();
+			snapshots.push((list, model));
+		}
+		for (list, model) in &snapshots {
+			assert_eq!(&list.to_vec(), model);
+			assert_eq!(list.len(), model.len());
+			assert_eq!(list.last(), model.last());
+			assert_eq!(list.get_back(0), model.last());
+		}
+	}
+
+	fn apply(model: &mut Vec<i32>, ops: &[DiffOp<'_, i32>]) {
+		for op in ops {
+			match *op {
+				DiffOp::Inserted { index, value } => model.insert(index, *value),
+				DiffOp::Removed { index } => {
+					model.remove(index);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn diff() {
+		let list = from_slice(&[0, 1, 2, 3, 4]);
+		let edited = list.insert(2, 10).unwrap();
+		assert_eq!(
+			list.diff(&edited),
+			[DiffOp::Inserted {
+				index: 2,
+				value: &10
+			}]
+		);
+		assert_eq!(edited.diff(&list), [DiffOp::Removed { index: 2 }]);
+		assert!(list.diff(&list).is_empty());
+		let edited = edited.remove(4).unwrap().replace(0, 0).unwrap();
+		let ops = list.diff(&edited);
+		assert_eq!(ops.len(), 4);
+		let mut model = list.to_vec();
+		apply(&mut model, &ops);
+		assert_eq!(model, edited.to_vec());
+		// The lists share no nodes, so everything is replaced even though the values are equal
+		let other = from_slice(&[0, 1]);
+		assert_eq!(list.diff(&other).len(), 7);
+	}
+
+	#[test]
+	fn diff_random() {
+		let mut snapshots = vec![from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9])];
+		for i in 0..100 {
+			let list = &snapshots[fastrand::usize(..snapshots.len())];
+			let list = if list.is_empty() || fastrand::bool() {
+				list.insert(fastrand::usize(..=list.len()), i).unwrap()
+			} else {
+				list.remove(fastrand::usize(..list.len())).unwrap()
+			};
+			snapshots.push(list);
+		}
+		for _ in 0..200 {
+			let a = &snapshots[fastrand::usize(..snapshots.len())];
+			let b = &snapshots[fastrand::usize(..snapshots.len())];
+			let mut model = a.to_vec();
+			apply(&mut model, &a.diff(b));
+			assert_eq!(model, b.to_vec());
+		}
+	}
+
+	#[test]
+	fn pop_single() {
+		// The values are returned as Rc, so T does not have to be Clone
+		struct NotClone(i32);
+
+		let list = PersistenLinkedList::new().prepend(NotClone(1));
+		let (front, value) = list.pop_front().unwrap();
+		assert_eq!(value.0, 1);
+		assert!(front.is_empty());
+		assert!(front.last().is_none());
+		let (back, value) = list.pop_back().unwrap();
+		assert_eq!(value.0, 1);
+		assert!(back.is_empty());
+		assert!(back.first().is_none());
+		assert_eq!(list.len(), 1);
+		assert_eq!(list.first().map(|value| value.0), Some(1));
+		let list = list.prepend(NotClone(0));
+		let (front, value) = list.pop_front().unwrap();
+		assert_eq!(value.0, 0);
+		assert_eq!(front.first().map(|value| value.0), Some(1));
+		let (back, value) = list.pop_back().unwrap();
+		assert_eq!(value.0, 1);
+		assert_eq!(back.last().map(|value| value.0), Some(0));
+	}
+
+	#[test]
+	fn stats() {
+		let n = 1000;
+		let mut list = PersistenLinkedList::new();
+		for i in 0..n {
+			list = list.prepend(i);
+		}
+		let stats = list.stats();
+		assert_eq!(stats.nodes, n);
+		// Every node except the first is changed once when a node is inserted in front of it
+		assert_eq!(stats.modified_nodes, n - 1);
+		assert_eq!(stats.pointer_entries, 4 * n + 2 * (n - 1));
+		// Reversing changes both pointers of every node
+		let reversed = list.reversed().reversed();
+		let stats = reversed.stats();
+		assert_eq!(stats.nodes, n);
+		assert_eq!(stats.modified_nodes, n);
+		assert!(stats.pointer_entries >= 4 * n + 2 * (n - 1) + 2 * 4 * n - 4);
+		assert_eq!(
+			PersistenLinkedList::<i32>::new().stats(),
+			Default::default()
+		);
+	}
+
+	#[test]
+	fn tail() {
+		let list = from_slice(&[0, 1, 2]);
+		let tail = list.tail().unwrap();
+		assert_eq!(tail.get(0), list.get(1));
+		assert_eq!(tail.to_vec(), [1, 2]);
+		assert_eq!(tail.len(), 2);
+		assert_eq!(tail.get_back(1), Some(&1));
+		assert_eq!(tail.get_back(2), None);
+		assert_eq!(tail.stats().nodes, list.stats().nodes);
+		let single = from_slice(&[0]).tail().unwrap();
+		assert!(single.is_empty());
+		assert!(single.tail().is_none());
+		assert_eq!(list.to_vec(), [0, 1, 2]);
+
+		// The tail shares the version, and reading backwards stops at its head
+		assert!(tail.version() == list.version());
+		let last = tail.tail().unwrap();
+		assert_eq!(
+			(last.len(), last.first(), last.last()),
+			(1, Some(&2), Some(&2))
+		);
+		assert_eq!(last.get_back(1), None);
+		assert!(last.pop_back().unwrap().0.is_empty());
+		assert_eq!(tail.reversed().to_vec(), [2, 1]);
+		assert_eq!(tail.reversed().get_back(2), None);
+		let mut cursor = tail.cursor_at(1).unwrap();
+		assert!(cursor.move_prev());
+		assert!(!cursor.move_prev());
+		assert_eq!(tail.prepend(5).to_vec(), [5, 1, 2]);
+		assert_eq!(tail.pop_front().unwrap().0.to_vec(), [2]);
+		let mut edited = tail.clone();
+		*edited.get_mut(0).unwrap() = 10;
+		assert_eq!(edited.to_vec(), [10, 2]);
+		assert_eq!(tail.to_vec(), [1, 2]);
+		assert_eq!(list.to_vec(), [0, 1, 2]);
+	}
+
+	#[test]
+	fn remove_first_last() {
+		let list = from_slice(&[0, 1, 2, 3]);
+		let first = list.remove_first().unwrap();
+		let last = list.remove_last().unwrap();
+		let both = first.remove_last().unwrap();
+		assert_eq!(first.to_vec(), [1, 2, 3]);
+		assert_eq!(last.to_vec(), [0, 1, 2]);
+		assert_eq!(both.to_vec(), [1, 2]);
+		assert_eq!((first.len(), last.len(), both.len()), (3, 3, 2));
+		assert_eq!(both.last(), Some(&2));
+		assert_eq!(last.get_back(0), Some(&2));
+		assert_eq!(list.to_vec(), [0, 1, 2, 3]);
+		assert_eq!(list.len(), 4);
+		let empty = from_slice(&[0]).remove_last().unwrap();
+		assert!(empty.is_empty());
+		assert!(empty.remove_first().is_none());
+		assert!(empty.remove_last().is_none());
+	}
+
+	#[test]
+	fn skip_index() {
+		let mut versions = vec![PersistenLinkedList::new()];
+		for i in 0..500 {
+			let list = versions.last().unwrap();
+			let list = if i % 5 == 4 {
+				list.remove(fastrand::usize(..list.len())).unwrap()
+			} else {
+				list.insert(fastrand::usize(..=list.len()), i).unwrap()
+			};
+			versions.push(list);
+		}
+		for list in versions.iter().step_by(7) {
+			let index = list.skip_index();
+			assert_eq!(index.len(), list.len());
+			for i in 0..=list.len() {
+				assert_eq!(index.get(i), list.get(i));
+			}
+		}
+		let index = versions[300].skip_index();
+		let _newer = versions[300].insert(0, 1000).unwrap();
+		assert!((0..versions[300].len()).all(|i| index.get(i) == versions[300].get(i)));
+		assert!(PersistenLinkedList::<i32>::new().skip_index().is_empty());
+		assert_eq!(PersistenLinkedList::<i32>::new().skip_index().get(0), None);
+		for (len, stride) in [
+			(0, 1),
+			(1, 1),
+			(4, 2),
+			(5, 3),
+			(9, 3),
+			(10, 4),
+			(10000, 100),
+		] {
+			let list = PersistenLinkedList::from_vec((0..len).collect());
+			assert_eq!(list.skip_index().stride, stride);
+		}
+	}
+
+	#[test]
+	fn branch_from_old_version() {
+		let v1 = from_slice(&[0, 1, 2]);
+		let v2 = v1.insert(1, 10).unwrap();
+		let v3 = v2.remove(0).unwrap();
+		let branch = v1.insert(1, 20).unwrap();
+		let branch2 = branch.replace(2, 30).unwrap();
+		let v4 = v3.insert(3, 40).unwrap();
+		assert_eq!(v1.to_vec(), [0, 1, 2]);
+		assert_eq!(v2.to_vec(), [0, 10, 1, 2]);
+		assert_eq!(v3.to_vec(), [10, 1, 2]);
+		assert_eq!(v4.to_vec(), [10, 1, 2, 40]);
+		assert_eq!(branch.to_vec(), [0, 20, 1, 2]);
+		assert_eq!(branch2.to_vec(), [0, 20, 30, 2]);
+		assert_eq!(v2.len(), 4);
+		assert_eq!(v3.len(), 3);
+		assert_eq!(branch2.len(), 4);
+		assert_eq!(v3.get_back(2), Some(&10));
+		assert_eq!(branch.get_back(3), Some(&0));
+	}
+
+	#[test]
+	fn map_at() {
+		let list = from_slice(&[1, 2, 3]);
+		let doubled = list.map_at(1, |x| x * 2).unwrap();
+		assert_eq!(doubled.to_vec(), [1, 4, 3]);
+		assert_eq!(doubled.map_at(2, |x| x * 2).unwrap().to_vec(), [1, 4, 6]);
+		assert_eq!(list.to_vec(), [1, 2, 3]);
+		assert!(list.map_at(3, |_| unreachable!()).is_none());
+
+		let words: PersistenLinkedList<String> = ["a", "b"].iter().map(|s| s.to_string()).collect();
+		let shouted = words.map_at(0, |s| s.to_uppercase()).unwrap();
+		assert_eq!(shouted.to_vec(), ["A", "b"]);
+		assert_eq!(words.to_vec(), ["a", "b"]);
+	}
+
+	#[test]
+	fn display() {
+		let list: PersistenLinkedList<_> = ["a", "b", "c"].into_iter().collect();
+		let inserted = list.insert(1, "x").unwrap();
+		let removed = list.remove(2).unwrap();
+		assert_eq!(format!("{inserted:#}"), "a -> x -> b -> c");
+		assert_eq!(format!("{removed:#}"), "a -> b");
+		assert_eq!(format!("{list:#}"), "a -> b -> c");
+		assert_eq!(format!("{:#}", from_slice(&[1])), "1");
+		assert_eq!(format!("{:#}", PersistenLinkedList::<i32>::new()), "");
+	}
+
+	#[test]
+	fn display_brackets() {
+		assert_eq!(format!("{}", PersistenLinkedList::<i32>::new()), "[]");
+		assert_eq!(format!("{}", from_slice(&[1])), "[1]");
+		let list = from_slice(&[1, 2, 3]);
+		assert_eq!(format!("{}", list), "[1, 2, 3]");
+		assert_eq!(format!("{}", list), format!("{:?}", vec![1, 2, 3]));
+		assert_eq!(format!("{}", list.remove(1).unwrap()), "[1, 3]");
+		let strings: PersistenLinkedList<String> =
+			["a", "b c"].iter().map(|s| s.to_string()).collect();
+		assert_eq!(format!("{}", strings), "[a, b c]");
+		let nested: PersistenLinkedList<_> = [list.clone(), PersistenLinkedList::new()]
+			.into_iter()
+			.collect();
+		assert_eq!(format!("{}", nested), "[[1, 2, 3], []]");
+	}
+
+	#[test]
+	fn swap_edges() {
+		let pair = from_slice(&[0, 1]);
+		let swapped = pair.swap(1, 0).unwrap();
+		assert_eq!(swapped.to_vec(), [1, 0]);
+		assert_eq!((swapped.first(), swapped.last()), (Some(&1), Some(&0)));
+		assert_eq!(swapped.get_back(1), Some(&1));
+		assert_eq!(pair.to_vec(), [0, 1]);
+
+		// The prev pointers must be updated as well as the next pointers
+		let list = from_slice(&[0, 1, 2, 3]);
+		for (i, j) in [(0, 3), (1, 2), (0, 1), (2, 3), (0, 2)] {
+			let swapped = list.swap(i, j).unwrap();
+			let mut expected = list.to_vec();
+			expected.swap(i, j);
+			let backwards: Vec<_> = (0..4)
+				.rev()
+				.map(|k| *swapped.get_back(k).unwrap())
+				.collect();
+			assert_eq!(backwards, expected);
+			assert_eq!(swapped.to_vec(), expected);
+		}
+		assert_eq!(list.to_vec(), [0, 1, 2, 3]);
+		let single = from_slice(&[0]);
+		let swapped = single.swap(0, 0).unwrap();
+		assert!(swapped.version() != single.version());
+		assert_eq!(swapped.to_vec(), [0]);
+		assert_eq!((swapped.first(), swapped.last()), (Some(&0), Some(&0)));
+		assert_eq!(swapped.insert(1, 1).unwrap().to_vec(), [0, 1]);
+		assert_eq!(single.to_vec(), [0]);
+	}
+
+	#[test]
+	fn hash() {
+		fn hash<T: std::hash::Hash>(value: &T) -> u64 {
+			use std::hash::{BuildHasher, RandomState};
+			thread_local! {
+				static STATE: RandomState = RandomState::new();
+			}
+			STATE.with(|state| state.hash_one(value))
+		}
+		let a = from_slice(&[1, 2, 3]);
+		let b = PersistenLinkedList::new()
+			.insert(0, 3)
+			.unwrap()
+			.prepend(1)
+			.insert(1, 2)
+			.unwrap();
+		assert!(a == b);
+		assert_eq!(hash(&a), hash(&b));
+		assert_ne!(hash(&a), hash(&a.reverse()));
+		assert_ne!(hash(&a), hash(&a.remove(2).unwrap()));
+		assert_eq!(hash(&a.remove(1).unwrap()), hash(&from_slice(&[1, 3])));
+
+		// The interior mutability of a list is in the shared structure, and the elements of a
+		// version never change, so the hash of a list does not change.
+		#[allow(clippy::mutable_key_type)]
+		let mut set = std::collections::HashSet::new();
+		set.insert(a.clone());
+		assert!(set.contains(&b));
+		assert!(!set.contains(&a.reverse()));
+	}
+
+	#[test]
+	fn view() {
+		let list = from_slice(&[1, 2, 3, 4]);
+		let removed = list.remove(1).unwrap();
+		assert_eq!(list.view()[0], *list.get(0).unwrap());
+		assert_eq!(removed.view()[1], 3);
+		assert_eq!(list.view().get(4), None);
+		assert_eq!(removed.view().len(), 3);
+		assert!(removed.view().iter().rev().eq(&[4, 3, 1]));
+		assert!(list.view().into_iter().eq(&[1, 2, 3, 4]));
+
+		let mut iter = list.view().iter();
+		assert_eq!(iter.len(), 4);
+		assert_eq!(iter.next(), Some(&1));
+		assert_eq!(iter.next_back(), Some(&4));
+		assert_eq!(iter.next_back(), Some(&3));
+		assert_eq!(iter.next(), Some(&2));
+		assert_eq!(iter.next(), None);
+		assert_eq!(iter.next_back(), None);
+		assert!(PersistenLinkedList::<i32>::new()
+			.view()
+			.iter()
+			.next_back()
+			.is_none());
+	}
+
+	#[test]
+	#[should_panic = "Index out of bounds. Index was 3 len was 3"]
+	fn view_out_of_bounds() {
+		let list = from_slice(&[1, 2, 3]);
+		let _ = list.view()[3];
+	}
+
+	#[test]
+	fn step_by() {
+		let list: PersistenLinkedList<_> = (0..10).collect();
+		assert!(list.step_by(1) == list);
+		assert_eq!(list.step_by(2).to_vec(), [0, 2, 4, 6, 8]);
+		assert_eq!(list.step_by(3).to_vec(), [0, 3, 6, 9]);
+		assert_eq!(list.step_by(11).to_vec(), [0]);
+		assert!(PersistenLinkedList::<i32>::new().step_by(2).is_empty());
+		let removed = list.remove(0).unwrap();
+		let stepped = removed.step_by(4);
+		assert_eq!(stepped.to_vec(), [1, 5, 9]);
+		assert_eq!(stepped.insert(1, 3).unwrap().to_vec(), [1, 3, 5, 9]);
+		assert_eq!(removed.to_vec(), (1..10).collect::<Vec<_>>());
+	}
+
+	#[test]
+	#[should_panic = "step must be non-zero"]
+	fn step_by_zero() {
+		from_slice(&[1]).step_by(0);
+	}
 }
@@ -2,109 +2,153 @@ use core::ptr::NonNull;
 
 use crate::version::PartialVersion;
 
+/// A link with a tag from a node to another node in every version from the version of the link
+/// until the next link with the same tag. A link to None removes the link from its version.
 pub struct Link<Node, Tag>
 where
 	Node: ?Sized,
 {
 	tag: Tag,
 	version: PartialVersion,
-	node_pointer: NonNull<Node>,
-	link_pointer: NonNull<Link<Node, Tag>>,
+	node_pointer: Option<NonNull<Node>>,
 }
 
+/// Node with a fixed number of links, which is made persistent by node copying. Links come in
+/// pairs, such that a node linked to another node with a tag is linked back with the reversed
+/// tag. When a node runs out of links it is copied, and the nodes linked to it are linked to the
+/// copy instead, which is why the reverse links are needed.
+///
+/// Links can only be modified in the newest version, which is the version after every version
+/// the nodes were modified in.
+///
+/// # Safety
+///
 /// The trait is marked unsafe since implementation of the copy function must return a
 /// dereferenciable pointer.
-pub unsafe trait Node<Tag: PartialEq + Eq + Clone + LinkTag> {
+pub unsafe trait Node<Tag: PartialEq + Eq + Clone + LinkTag>: Sized {
 	fn link_container_mut(&mut self) -> &mut [Option<Link<Self, Tag>>];
 
 	fn link_container(&self) -> &[Option<Link<Self, Tag>>];
 
+	/// Allocates a copy of the node without links and stores it as the copy of the node.
 	fn copy(&mut self) -> NonNull<Self>;
 
 	fn copy_pointer(&self) -> Option<NonNull<Self>>;
 
-	fn current_version(&mut self, _version: PartialVersion) -> &mut Self {
-		self.copy_pointer().map(|mut pointer| unsafe { pointer.as_mut() }).unwrap_or(self)
+	/// Returns the newest copy of the node, which is the node that is modified in the newest
+	/// version. Older versions are still read from the node itself.
+	fn current(mut this: NonNull<Self>) -> NonNull<Self> {
+		while let Some(copy) = unsafe { this.as_ref() }.copy_pointer() {
+			this = copy;
+		}
+		this
 	}
 
-	fn copy_and_prepare(&mut self, version: PartialVersion) -> NonNull<Self> {
-		let mut copy = self.copy();
-		let container = unsafe { copy.as_mut() }.link_container_mut();
-		let mut to_move = Vec::new();
-		for i in 0..container.len() {
-			if let Some(current) = &container[i] {
-				if container
-					.iter()
-					.filter_map(Option::as_ref)
-					.all(|link| link.tag != current.tag || link.version <= current.version)
-				{
-					to_move.push(i);
+	/// Copies the node in the version. The copy gets the links of the node in the version, and
+	/// the nodes linked to the node are linked to the copy from the version.
+	fn copy_and_prepare(mut this: NonNull<Self>, version: PartialVersion) -> NonNull<Self> {
+		let node = unsafe { this.as_ref() };
+		let mut links: Vec<(Tag, NonNull<Self>)> = Vec::new();
+		for link in node.link_container().iter().flatten() {
+			if links.iter().any(|(tag, _)| *tag == link.tag) {
+				continue;
+			}
+			// Links which are not linked back are being replaced, so they are not copied
+			if let Some(pointer) = node.get(link.tag.clone(), version) {
+				let reverse = unsafe { Self::current(pointer).as_ref() }
+					.get(link.tag.clone().reverse(), version);
+				if reverse.map(Self::current) == Some(this) {
+					links.push((link.tag.clone(), pointer));
 				}
 			}
 		}
-		for i in to_move {
-			let Some(link) = &mut container[i] else {
-				unreachable!()
-			};
-			if link.version == version {
-				let free = unsafe { copy.as_mut() }.link_container_mut()
-					.iter_mut().find(|link| link.is_none())
-					.expect("It has just been cloned. This means that the capacity is less than the tag size");
-				*free = Some(Link {
-					tag: link.tag.clone(),
-					version,
-					node_pointer: link.node_pointer,
-					link_pointer: link.link_pointer,
-				});
-				unsafe { link.link_pointer.as_mut() }.node_pointer = copy;
-				unsafe { link.link_pointer.as_mut() }.link_pointer =
-					NonNull::from(free.as_mut().expect("was just intialized to Some"));
-				container[i] = None;
-			} else {
-				unsafe { copy.as_mut() }.add(link.tag.clone(), link.node_pointer, version, false);
-			}
+		let copy = unsafe { this.as_mut() }.copy();
+		for (tag, pointer) in links {
+			Self::add(copy, tag, Some(pointer), version);
 		}
 		copy
 	}
 
-	fn add(
-		&mut self,
+	/// Sets the link with the tag in the version without setting the reverse link. A link set
+	/// earlier in the same version is overwritten. Copies the node if it has no free links.
+	/// Returns the node which links to the pointer in the version.
+	fn write(
+		this: NonNull<Self>,
 		tag: Tag,
-		mut pointer: NonNull<Self>,
+		pointer: Option<NonNull<Self>>,
 		version: PartialVersion,
-		reverse: bool,
-	) -> (NonNull<Self>, NonNull<Link<Self, Tag>>) {
-		if let Some(free) = self
-			.link_container_mut()
-			.iter_mut()
-			.find(|link| link.is_none())
-		{
-			*free = Some(Link {
-				tag: tag.clone(),
-				version,
-				node_pointer: pointer,
-				link_pointer: NonNull::dangling(),
-			});
-			let mut link_non_null =
-				NonNull::from(free.as_mut().expect("was just initialized to Some"));
-
-			if !reverse {
-				let (pointer, mut link_pointer) = unsafe { pointer.as_mut() }.add(
-					tag.reverse(),
-					unsafe { NonNull::new_unchecked(self as *mut _) },
+	) -> NonNull<Self> {
+		let mut this = Self::current(this);
+		let node = unsafe { this.as_mut() };
+		if node.get(tag.clone(), version) == pointer {
+			return this;
+		}
+		assert!(
+			node.link_container()
+				.iter()
+				.flatten()
+				.all(|link| link.version <= version),
+			"links can only be modified in the newest version"
+		);
+		let container = node.link_container_mut();
+		let free = container
+			.iter()
+			.position(|link| matches!(link, Some(link) if link.tag == tag && link.version == version))
+			.or_else(|| container.iter().position(Option::is_none));
+		match free {
+			Some(i) => {
+				container[i] = Some(Link {
+					tag,
 					version,
-					false,
-				);
-				unsafe { link_non_null.as_mut() }.node_pointer = pointer;
-				unsafe { link_non_null.as_mut() }.link_pointer = link_pointer;
-				unsafe { link_pointer.as_mut() }.link_pointer = link_non_null;
+					node_pointer: pointer,
+				});
+				this
 			}
+			None => {
+				let copy = Self::copy_and_prepare(this, version);
+				Self::write(copy, tag, pointer, version)
+			}
+		}
+	}
 
-			let self_non_null = NonNull::from(self);
-			(self_non_null, link_non_null)
-		} else {
-			let mut copy = self.copy_and_prepare(version);
-			unsafe { copy.as_mut() }.add(tag, pointer, version, reverse)
+	/// Links the node to the pointer with the tag in the version, and the pointer back to the
+	/// node with the reversed tag. The nodes previously linked with those tags are unlinked, so
+	/// links stay in pairs. Linking to None removes the link.
+	fn add(this: NonNull<Self>, tag: Tag, pointer: Option<NonNull<Self>>, version: PartialVersion) {
+		let reverse = tag.clone().reverse();
+		let linked_back = |from: NonNull<Self>, tag: Tag, to: NonNull<Self>| {
+			unsafe { Self::current(from).as_ref() }
+				.get(tag, version)
+				.map(Self::current)
+				== Some(Self::current(to))
+		};
+		let old = unsafe { Self::current(this).as_ref() }.get(tag.clone(), version);
+		if let Some(old) = old {
+			let relinked = pointer.map(Self::current) == Some(Self::current(old));
+			if !relinked && linked_back(old, reverse.clone(), this) {
+				Self::write(old, reverse.clone(), None, version);
+			}
+		}
+		let Some(pointer) = pointer else {
+			Self::write(this, tag, None, version);
+			return;
+		};
+		let old = unsafe { Self::current(pointer).as_ref() }.get(reverse.clone(), version);
+		if let Some(old) = old {
+			if Self::current(old) != Self::current(this) && linked_back(old, tag.clone(), pointer) {
+				Self::write(old, tag.clone(), None, version);
+			}
+		}
+		// Writing a link may copy either node, in which case the links are written again
+		let mut this = Self::current(this);
+		loop {
+			let pointer = Self::current(pointer);
+			this = Self::write(this, tag.clone(), Some(pointer), version);
+			let written = Self::write(pointer, reverse.clone(), Some(this), version);
+			if written == pointer && Self::current(this) == this {
+				break;
+			}
+			this = Self::current(this);
 		}
 	}
 
@@ -114,7 +158,7 @@ pub unsafe trait Node<Tag: PartialEq + Eq + Clone + LinkTag> {
 			.filter_map(Option::as_ref)
 			.filter(|link| link.tag == tag && link.version <= version)
 			.max_by_key(|link| link.version)
-			.map(|link| link.node_pointer)
+			.and_then(|link| link.node_pointer)
 	}
 }
 
@@ -0,0 +1,547 @@
+use std::{
+	cmp::Ordering,
+	mem::ManuallyDrop,
+	sync::{
+		atomic::{self, AtomicU64},
+		Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+	},
+};
+
+use crate::{
+	version::{OwnedVersionList, Version},
+	Iter, PersistenLinkedList,
+};
+
+/// Thread safe persistent doubly linked list.
+///
+/// `PersistenLinkedList` is neither `Send` nor `Sync`. The nodes and versions are shared between
+/// all handles of a list through raw pointers, and creating a new version writes to nodes and to
+/// the version list that are reachable from every other handle, including handles of old
+/// versions. The values are shared through `Rc`, whose reference counts are not atomic.
+///
+/// This wraps a list and guards everything it shares with a lock which is shared by all handles
+/// of the list. Reading a version only reads the shared structure and takes the lock for reading,
+/// so different threads can read versions concurrently. Creating, cloning and dropping a handle
+/// takes the lock for writing. The values are never handed out as `Rc`, so the reference counts
+/// are only touched while the lock is held for writing.
+///
+/// The lock is not re-entrant. While an iterator returned by `iter` is alive, it holds the lock
+/// for reading, so creating, cloning or dropping a handle of the list on the same thread
+/// deadlocks, and taking the lock for reading again may deadlock or panic. Only `len` does not
+/// take the lock, so it can be called while an iterator is alive.
+///
+/// This is a wrapper around the single threaded list rather than a list built on `Arc`, so every
+/// handle of the list contends on the same lock.
+pub struct ArcPersistentLinkedList<T> {
+	list: ManuallyDrop<PersistenLinkedList<T>>,
+	lock: Arc<RwLock<()>>,
+}
+
+// SAFETY: Everything shared between handles is only accessed while holding the shared lock, and
+// it is only written to while holding it for writing. T is shared between threads through
+// references and can be moved to another thread in insert.
+unsafe impl<T: Send + Sync> Send for ArcPersistentLinkedList<T> {}
+
+unsafe impl<T: Send + Sync> Sync for ArcPersistentLinkedList<T> {}
+
+impl<T> Default for ArcPersistentLinkedList<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T> ArcPersistentLinkedList<T> {
+	pub fn new() -> ArcPersistentLinkedList<T> {
+		ArcPersistentLinkedList::from_list(PersistenLinkedList::new())
+	}
+
+	/// Creates a new list with a new version tree containing the elements of the vec.
+	pub fn from_vec(v: Vec<T>) -> ArcPersistentLinkedList<T> {
+		ArcPersistentLinkedList::from_list(PersistenLinkedList::from_vec(v))
+	}
+
+	/// Wraps a list with a new lock. The list must not share anything with other handles.
+	fn from_list(list: PersistenLinkedList<T>) -> ArcPersistentLinkedList<T> {
+		ArcPersistentLinkedList {
+			list: ManuallyDrop::new(list),
+			lock: Arc::new(RwLock::new(())),
+		}
+	}
+
+	/// Wraps a new version of this list with the lock of this list.
+	fn with_list(&self, list: PersistenLinkedList<T>) -> ArcPersistentLinkedList<T> {
+		ArcPersistentLinkedList {
+			list: ManuallyDrop::new(list),
+			lock: self.lock.clone(),
+		}
+	}
+
+	fn read(&self) -> RwLockReadGuard<'_, ()> {
+		// The lock guards no data, so a panic while holding it leaves nothing inconsistent
+		self.lock.read().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn write(&self) -> RwLockWriteGuard<'_, ()> {
+		self.lock.write().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	pub fn get(&self, index: usize) -> Option<&T> {
+		let _guard = self.read();
+		self.list.get(index)
+	}
+
+	/// Inserts the value at the given index in a new version. Returns None if the index is
+	/// greater than the length of the list.
+	pub fn insert(&self, index: usize, value: T) -> Option<ArcPersistentLinkedList<T>> {
+		let _guard = self.write();
+		let list = self.list.insert(index, value)?;
+		Some(self.with_list(list))
+	}
+
+	/// Removes the element at the given index in a new version. Returns None if the index is out
+	/// of bounds.
+	pub fn remove(&self, index: usize) -> Option<ArcPersistentLinkedList<T>> {
+		let _guard = self.write();
+		let list = self.list.remove(index)?;
+		Some(self.with_list(list))
+	}
+
+	/// Replaces the value at the given index in a new version. Returns None if the index is out
+	/// of bounds.
+	pub fn replace(&self, index: usize, value: T) -> Option<ArcPersistentLinkedList<T>> {
+		let _guard = self.write();
+		let list = self.list.replace(index, value)?;
+		Some(self.with_list(list))
+	}
+
+	/// Returns a new version with the items appended to the end of this version. All items are
+	/// added in a single version.
+	pub fn extend<I: IntoIterator<Item = T>>(&self, iter: I) -> ArcPersistentLinkedList<T> {
+		let _guard = self.write();
+		self.with_list(self.list.extend(iter))
+	}
+
+	/// Returns the number of elements in this version. The length is stored in the handle and
+	/// never changes, so this does not take the lock.
+	pub fn len(&self) -> usize {
+		self.list.len()
+	}
+
+	/// Returns true if this version of the list has no elements.
+	pub fn is_empty(&self) -> bool {
+		let _guard = self.read();
+		self.list.is_empty()
+	}
+
+	/// Returns the first element of this version.
+	pub fn first(&self) -> Option<&T> {
+		let _guard = self.read();
+		self.list.first()
+	}
+
+	/// Returns the last element of this version.
+	pub fn last(&self) -> Option<&T> {
+		let _guard = self.read();
+		self.list.last()
+	}
+
+	/// Returns a vec containing clones of the elements of this version.
+	pub fn to_vec(&self) -> Vec<T>
+	where
+		T: Clone,
+	{
+		let _guard = self.read();
+		self.list.to_vec()
+	}
+
+	/// Returns an iterator over the elements of this version. The lock is held for reading until
+	/// the iterator is dropped, so creating new versions of the list on the same thread while the
+	/// iterator is alive deadlocks.
+	pub fn iter(&self) -> SyncIter<'_, T> {
+		SyncIter {
+			_guard: self.read(),
+			iter: self.list.iter(),
+		}
+	}
+}
+
+impl<T> Clone for ArcPersistentLinkedList<T> {
+	fn clone(&self) -> Self {
+		let _guard = self.write();
+		self.with_list(PersistenLinkedList::clone(&self.list))
+	}
+}
+
+impl<T> Drop for ArcPersistentLinkedList<T> {
+	fn drop(&mut self) {
+		let _guard = self.lock.write().unwrap_or_else(PoisonError::into_inner);
+		// SAFETY: The list is not used after this
+		unsafe { ManuallyDrop::drop(&mut self.list) };
+	}
+}
+
+impl<T> FromIterator<T> for ArcPersistentLinkedList<T> {
+	/// Creates a new list with a new version tree containing the items.
+	fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+		ArcPersistentLinkedList::from_list(iter.into_iter().collect())
+	}
+}
+
+impl<'a, T> IntoIterator for &'a ArcPersistentLinkedList<T> {
+	type Item = &'a T;
+
+	type IntoIter = SyncIter<'a, T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// Iterator over the elements of a version of a thread safe list. Holds the lock of the list for
+/// reading.
+pub struct SyncIter<'a, T> {
+	_guard: RwLockReadGuard<'a, ()>,
+	iter: Iter<'a, T>,
+}
+
+impl<'a, T> Iterator for SyncIter<'a, T> {
+	type Item = &'a T;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.iter.next()
+	}
+}
+
+/// Thread safe version.
+///
+/// `Version` is neither `Send` nor `Sync`, as inserting a version writes to the version list
+/// shared by all versions of the list, and may relabel every version of the list. Comparing
+/// versions reads the labels, so it must not race an insertion.
+///
+/// This wraps a version with a lock shared by all versions of the list. Inserting a version takes
+/// the lock for writing, and `is_leaf` takes it for reading. Comparing versions does not take the
+/// lock. It reads the labels as a snapshot with a sequence lock, and retries if a version was
+/// inserted while it read them. The version list is owned by its versions and freed when the last
+/// version is dropped.
+pub struct SyncVersion<const B: usize = 64> {
+	version: Version<B>,
+	list: Arc<SyncVersionList<B>>,
+}
+
+struct SyncVersionList<const B: usize> {
+	lock: RwLock<()>,
+	// Incremented before and after a version is inserted, so it is odd while the labels are
+	// written
+	sequence: AtomicU64,
+	_owner: OwnedVersionList<B>,
+}
+
+// SAFETY: The version list is not tied to a thread, it is only reached through raw pointers which
+// are not Send. Every write to the version list happens in insert_after or insert_before while
+// holding the lock for writing. is_leaf reads the list while holding the lock for reading.
+// Comparisons read the list without the lock, but they only read the labels and the parents of
+// the nodes, which are atomic, and the parents of super nodes and the base of the list, which are
+// only written when versions are deleted, which a SyncVersion never does. Inserting a version
+// never frees a node, so the pointers a comparison follows stay valid. The version list is freed
+// by the owner when the last version is dropped, at which point no other thread can access it.
+unsafe impl<const B: usize> Send for SyncVersionList<B> {}
+
+// SAFETY: See the Send impl above.
+unsafe impl<const B: usize> Sync for SyncVersionList<B> {}
+
+// SAFETY: The version is a pointer into the version list, which is shared through the Arc, so the
+// version can be sent and shared exactly when the version list can, see above.
+unsafe impl<const B: usize> Send for SyncVersion<B> {}
+
+// SAFETY: See the Send impl above.
+unsafe impl<const B: usize> Sync for SyncVersion<B> {}
+
+impl Default for SyncVersion {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl SyncVersion {
+	pub fn new() -> SyncVersion {
+		SyncVersion::with_block_size()
+	}
+}
+
+impl<const B: usize> SyncVersion<B> {
+	/// Creates a new version and the associated version list with the given super node size, see
+	/// `PartialVersion::with_block_size`.
+	pub fn with_block_size() -> SyncVersion<B> {
+		let version = Version::with_block_size();
+		// SAFETY: The owner is dropped with the last version of the list, as every version holds
+		// the owner
+		let owner = unsafe { OwnedVersionList::new(version.primary) };
+		SyncVersion {
+			version,
+			list: Arc::new(SyncVersionList {
+				lock: RwLock::new(()),
+				sequence: AtomicU64::new(0),
+				_owner: owner,
+			}),
+		}
+	}
+
+	fn read(&self) -> RwLockReadGuard<'_, ()> {
+		// The lock guards no data, so a panic while holding it leaves nothing inconsistent
+		self.list
+			.lock
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Inserts a version with the function while holding the lock for writing, and marks the
+	/// labels as written in the meantime, see `try_cmp`.
+	fn insert(&self, insert: impl FnOnce(Version<B>) -> Version<B>) -> SyncVersion<B> {
+		let _guard = self
+			.list
+			.lock
+			.write()
+			.unwrap_or_else(PoisonError::into_inner);
+		let sequence = &self.list.sequence;
+		sequence.fetch_add(1, atomic::Ordering::Relaxed);
+		// The labels must not be written before the sequence is odd
+		atomic::fence(atomic::Ordering::Release);
+		let version = insert(self.version);
+		sequence.fetch_add(1, atomic::Ordering::Release);
+		SyncVersion {
+			version,
+			list: self.list.clone(),
+		}
+	}
+
+	/// Inserts a new version directly after this version and returns it, see
+	/// `Version::insert_after`.
+	pub fn insert_after(&self) -> SyncVersion<B> {
+		self.insert(Version::insert_after)
+	}
+
+	/// Inserts a new version directly before this version and returns it, see
+	/// `Version::insert_before`.
+	pub fn insert_before(&self) -> SyncVersion<B> {
+		self.insert(Version::insert_before)
+	}
+
+	/// Compares the versions if they are from the same version list. Returns None if they are
+	/// from different version lists.
+	///
+	/// This does not take the lock. The labels are read while the sequence is even and unchanged,
+	/// so no version was inserted while they were read, otherwise they are read again.
+	pub fn try_cmp(&self, other: &Self) -> Option<Ordering> {
+		if !Arc::ptr_eq(&self.list, &other.list) {
+			return None;
+		}
+		let sequence = &self.list.sequence;
+		loop {
+			let start = sequence.load(atomic::Ordering::Acquire);
+			if start.is_multiple_of(2) {
+				let ordering = self.version.cmp(&other.version);
+				// The labels must be read before the sequence is read again
+				atomic::fence(atomic::Ordering::Acquire);
+				if sequence.load(atomic::Ordering::Relaxed) == start {
+					return Some(ordering);
+				}
+			}
+			std::hint::spin_loop();
+		}
+	}
+
+	/// Returns true if no version has been inserted after this version, see `Version::is_leaf`.
+	pub fn is_leaf(&self) -> bool {
+		let _guard = self.read();
+		self.version.is_leaf()
+	}
+}
+
+impl<const B: usize> Clone for SyncVersion<B> {
+	fn clone(&self) -> Self {
+		SyncVersion {
+			version: self.version,
+			list: self.list.clone(),
+		}
+	}
+}
+
+/// Versions are equal if they are the same version, which does not read the labels, so this
+/// neither takes the lock nor retries. Versions from different version lists are not equal.
+impl<const B: usize> PartialEq for SyncVersion<B> {
+	fn eq(&self, other: &Self) -> bool {
+		self.version == other.version
+	}
+}
+
+impl<const B: usize> Eq for SyncVersion<B> {}
+
+impl<const B: usize> PartialOrd for SyncVersion<B> {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// Comparing versions from different version lists panics. Use `try_cmp` if the versions may be
+/// from different version lists.
+impl<const B: usize> Ord for SyncVersion<B> {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.try_cmp(other)
+			.expect("compared versions from different version lists")
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{ArcPersistentLinkedList, SyncVersion};
+
+	#[test]
+	fn versions() {
+		let list: ArcPersistentLinkedList<_> = (0..3).collect();
+		let inserted = list.insert(1, 10).unwrap();
+		let removed = inserted.remove(0).unwrap();
+		assert_eq!(list.to_vec(), [0, 1, 2]);
+		assert_eq!(inserted.to_vec(), [0, 10, 1, 2]);
+		assert_eq!(removed.to_vec(), [10, 1, 2]);
+		assert_eq!(removed.replace(2, 5).unwrap().to_vec(), [10, 1, 5]);
+		assert_eq!(inserted.len(), 4);
+		assert_eq!(inserted.get(1), Some(&10));
+		assert_eq!(removed.first(), Some(&10));
+		assert_eq!(removed.last(), Some(&2));
+		assert!(ArcPersistentLinkedList::<i32>::new().is_empty());
+	}
+
+	#[test]
+	fn threads_read_frozen_versions() {
+		let (versions_count, inserts) = if cfg!(miri) { (5, 20) } else { (50, 1000) };
+		let mut versions = vec![ArcPersistentLinkedList::new()];
+		for i in 0..versions_count {
+			let list = versions.last().unwrap().insert(0, i).unwrap();
+			versions.push(list);
+		}
+		std::thread::scope(|s| {
+			for (i, list) in versions.iter().enumerate() {
+				s.spawn(move || {
+					let expected: Vec<_> = (0..i).rev().collect();
+					for _ in 0..3 {
+						let iter = list.iter();
+						// The length is read while the iterator holds the lock for reading
+						assert_eq!(list.len(), i);
+						assert_eq!(iter.copied().collect::<Vec<_>>(), expected);
+					}
+				});
+			}
+			// Keep creating versions of the list while the threads read
+			let mut list = versions[2].clone();
+			for i in 0..inserts {
+				list = list
+					.insert(fastrand::usize(..=list.len()), 100 + i)
+					.unwrap();
+			}
+		});
+	}
+
+	#[test]
+	fn send_snapshots() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<ArcPersistentLinkedList<String>>();
+
+		let (threads, extends) = if cfg!(miri) { (2, 10) } else { (8, 500) };
+		let mut list = ArcPersistentLinkedList::from_vec((0..10).collect());
+		let snapshot = list.clone();
+		let handles: Vec<_> = (0..threads)
+			.map(|_| {
+				let snapshot = snapshot.clone();
+				std::thread::spawn(move || {
+					for _ in 0..10 {
+						let sum: i32 = snapshot.iter().sum();
+						assert_eq!(sum, 45);
+					}
+					snapshot
+				})
+			})
+			.collect();
+		for i in 0..extends {
+			list = list.extend([i]).remove(0).unwrap();
+		}
+		for handle in handles {
+			let snapshot = handle.join().unwrap();
+			assert_eq!(snapshot.to_vec(), (0..10).collect::<Vec<_>>());
+		}
+		assert_eq!(list.len(), 10);
+		assert_eq!(snapshot.to_vec(), (0..10).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn sync_versions() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<SyncVersion>();
+
+		let (threads, inserts) = if cfg!(miri) { (3, 20) } else { (8, 2000) };
+		let root = SyncVersion::new();
+		let children: Vec<_> = (0..threads).map(|_| root.insert_after()).collect();
+		let handles: Vec<_> = children
+			.into_iter()
+			.map(|child| {
+				std::thread::spawn(move || {
+					// Every version is recorded with its parent
+					let mut versions = vec![(child.clone(), None)];
+					for _ in 0..inserts {
+						let i = fastrand::usize(..versions.len());
+						let parent = versions[i].0.clone();
+						let version = if fastrand::bool() {
+							parent.insert_after()
+						} else {
+							parent.insert_before()
+						};
+						let parent = (version > parent).then_some(parent);
+						versions.push((version, parent));
+					}
+					versions
+				})
+			})
+			.collect();
+		let versions: Vec<_> = handles
+			.into_iter()
+			.flat_map(|handle| handle.join().unwrap())
+			.collect();
+		for (version, parent) in &versions {
+			assert!(root < *version);
+			if let Some(parent) = parent {
+				assert!(parent < version);
+				assert!(!parent.is_leaf());
+			}
+		}
+		let mut sorted: Vec<_> = versions.into_iter().map(|(version, _)| version).collect();
+		sorted.sort();
+		assert!(sorted.windows(2).all(|w| w[0] < w[1]));
+		assert!(SyncVersion::new().try_cmp(&root).is_none());
+		assert!(SyncVersion::new() != root);
+	}
+
+	#[test]
+	fn compare_while_relabeling() {
+		let (compares, inserts) = if cfg!(miri) {
+			(20, 100)
+		} else {
+			(20000, 20000)
+		};
+		let root = SyncVersion::<4>::with_block_size();
+		let versions: Vec<_> = (0..10).map(|_| root.insert_after()).collect();
+		std::thread::scope(|s| {
+			for _ in 0..2 {
+				s.spawn(|| {
+					for _ in 0..compares {
+						// Every version is inserted directly after the root, so the newest is first
+						assert!(versions.windows(2).all(|w| w[0] > w[1]));
+						assert!(root < versions[0] && versions[0] == versions[0]);
+					}
+				});
+			}
+			// Inserting directly after the root relabels the versions after it many times
+			for _ in 0..inserts {
+				root.insert_after();
+			}
+		});
+	}
+}
@@ -1,7 +1,58 @@
 use core::ptr::NonNull;
 
+#[cfg(feature = "alloc-stats")]
+thread_local! {
+	static ALLOC_COUNTER: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+	static FREE_COUNTER: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
 /// Allocate t in the heap and return a pointer to it.
 pub fn alloc<T>(t: T) -> NonNull<T> {
+	count_alloc();
 	// SAFETY: The pointer is valid as it comes from a box
 	unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(t))) }
 }
+
+/// Free a value allocated with `alloc`.
+///
+/// # Safety
+/// The pointer must come from `alloc` and must not be used after this.
+pub unsafe fn free<T>(ptr: NonNull<T>) {
+	count_free();
+	// SAFETY: The pointer comes from a box as it was allocated with `alloc`
+	drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+}
+
+/// Counts an allocation of a node if the `alloc-stats` feature is enabled.
+pub fn count_alloc() {
+	#[cfg(feature = "alloc-stats")]
+	ALLOC_COUNTER.with(|counter| counter.set(counter.get() + 1));
+}
+
+/// Counts a free of a node if the `alloc-stats` feature is enabled.
+pub fn count_free() {
+	#[cfg(feature = "alloc-stats")]
+	FREE_COUNTER.with(|counter| counter.set(counter.get() + 1));
+}
+
+/// Returns the number of nodes allocated by the data structures on this thread since the last
+/// call to `reset_alloc_count`. The counter is per thread such that concurrent tests do not
+/// affect each other.
+#[cfg(feature = "alloc-stats")]
+pub fn alloc_count() -> usize {
+	ALLOC_COUNTER.with(|counter| counter.get())
+}
+
+/// Returns the number of nodes freed by the data structures on this thread since the last call
+/// to `reset_alloc_count`.
+#[cfg(feature = "alloc-stats")]
+pub fn free_count() -> usize {
+	FREE_COUNTER.with(|counter| counter.get())
+}
+
+/// Resets the allocation and free counters of this thread.
+#[cfg(feature = "alloc-stats")]
+pub fn reset_alloc_count() {
+	ALLOC_COUNTER.with(|counter| counter.set(0));
+	FREE_COUNTER.with(|counter| counter.set(0));
+}
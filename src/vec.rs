@@ -25,6 +25,16 @@ impl<T: ?Sized> Vec<T> {
 		}
 	}
 
+	/// Creates a vec containing the items in a version after the base version. Returns the vec
+	/// and the version containing the items.
+	pub fn from_vec(items: vec::Vec<Box<T>>, base: Version) -> (Vec<T>, Version) {
+		let mut vec = Vec::new();
+		let version = items
+			.into_iter()
+			.fold(base, |version, item| vec.push_after(item, version));
+		(vec, version)
+	}
+
 	pub fn push_after(&mut self, value: Box<T>, version: Version) -> Version {
 		let len = self.len(version);
 		if len == self.vec.len() {
@@ -40,6 +50,30 @@ impl<T: ?Sized> Vec<T> {
 		self.set_len_after(version, len - 1)
 	}
 
+	/// Removes the last element in a new version after the given version. Returns the new version
+	/// and the removed element, or None if the vec is empty in the given version.
+	pub fn pop_value_after(&mut self, version: Version) -> Option<(Version, &T)> {
+		let len = self.len(version);
+		if len == 0 {
+			return None;
+		}
+		let new_version = self.set_len_after(version, len - 1);
+		let value = self.vec[len - 1]
+			.get(version)
+			.expect("must be initialized in this cell as the len is greater for this version");
+		Some((new_version, value))
+	}
+
+	/// Sets the value at the index in a new version after the given version. Panics if the index
+	/// is out of bounds.
+	pub fn set_after(&mut self, index: usize, value: Box<T>, version: Version) -> Version {
+		let len = self.len(version);
+		if index >= len {
+			panic!("Index out of bounds. Index was {} len was {}", index, len);
+		}
+		self.vec[index].insert_after(version, value)
+	}
+
 	pub fn view(&self, version: Version) -> VecView<'_, T> {
 		VecView {
 			inner: self,
@@ -50,7 +84,7 @@ impl<T: ?Sized> Vec<T> {
 	pub fn len(&self, version: Version) -> usize {
 		// If the version is before the vector was created this will return None, so
 		// therefore unwrap_or(0)
-		self.len.get(version).cloned().unwrap_or(0)
+		self.len.get_cloned(version).unwrap_or(0)
 	}
 
 	fn set_len_after(&mut self, version: Version, len: usize) -> Version {
@@ -58,12 +92,137 @@ impl<T: ?Sized> Vec<T> {
 	}
 }
 
+impl<T: Clone> Vec<T> {
+	/// Creates a vec containing clones of the items in a version after the base version. Returns
+	/// the vec and the version containing the items.
+	pub fn from_slice(items: &[T], base: Version) -> (Vec<T>, Version) {
+		Vec::from_vec(
+			items.iter().map(|item| Box::new(item.clone())).collect(),
+			base,
+		)
+	}
+
+	/// Inserts the value at the index in a new version after the given version, shifting all
+	/// elements after it to the right. Runs in O((n - index) log m) time. Panics if the index is
+	/// greater than the length.
+	pub fn insert_after(&mut self, index: usize, value: Box<T>, version: Version) -> Version {
+		let len = self.len(version);
+		if index > len {
+			panic!("Index out of bounds. Index was {} len was {}", index, len);
+		}
+		if len == self.vec.len() {
+			self.vec.push(PersistentCell::new());
+		}
+		let mut new_version = version;
+		for i in (index..len).rev() {
+			let value = self.get_cloned(i, version);
+			new_version = self.vec[i + 1].insert_after(new_version, value);
+		}
+		new_version = self.vec[index].insert_after(new_version, value);
+		self.set_len_after(new_version, len + 1)
+	}
+
+	/// Removes the value at the index in a new version after the given version, shifting all
+	/// elements after it to the left. Runs in O((n - index) log m) time. Panics if the index is
+	/// out of bounds.
+	pub fn remove_after(&mut self, index: usize, version: Version) -> Version {
+		let len = self.len(version);
+		if index >= len {
+			panic!("Index out of bounds. Index was {} len was {}", index, len);
+		}
+		let mut new_version = version;
+		for i in index..len - 1 {
+			let value = self.get_cloned(i + 1, version);
+			new_version = self.vec[i].insert_after(new_version, value);
+		}
+		self.set_len_after(new_version, len - 1)
+	}
+
+	fn get_cloned(&self, index: usize, version: Version) -> Box<T> {
+		Box::new(
+			self.vec[index]
+				.get(version)
+				.expect("must be initialized in this cell as the len is greater for this version")
+				.clone(),
+		)
+	}
+}
+
 /// A view into a specific version of a vec
 pub struct VecView<'a, T: ?Sized> {
 	inner: &'a Vec<T>,
 	version: Version,
 }
 
+impl<T: ?Sized> VecView<'_, T> {
+	/// Gets the element at the index in this version. Returns None if the index is out of
+	/// bounds.
+	pub fn get(&self, index: usize) -> Option<&T> {
+		if index >= self.len() {
+			None
+		} else {
+			self.inner.vec[index].get(self.version)
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.inner.len(self.version)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns an iterator over the elements in this version.
+	pub fn iter(&self) -> Iter<'_, T> {
+		Iter {
+			inner: self.inner,
+			version: self.version,
+			index: 0,
+			len: self.len(),
+		}
+	}
+}
+
+impl<'a, T: ?Sized> IntoIterator for &'a VecView<'a, T> {
+	type Item = &'a T;
+
+	type IntoIter = Iter<'a, T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// Iterator over the elements of a version of a vec
+pub struct Iter<'a, T: ?Sized> {
+	inner: &'a Vec<T>,
+	version: Version,
+	index: usize,
+	len: usize,
+}
+
+impl<'a, T: ?Sized> Iterator for Iter<'a, T> {
+	type Item = &'a T;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.index >= self.len {
+			None
+		} else {
+			let value = self.inner.vec[self.index]
+				.get(self.version)
+				.expect("must be initialized in this cell as the len is greater for this version");
+			self.index += 1;
+			Some(value)
+		}
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let remaining = self.len - self.index;
+		(remaining, Some(remaining))
+	}
+}
+
 impl<T> Index<usize> for VecView<'_, T> {
 	type Output = T;
 
@@ -78,3 +237,145 @@ impl<T> Index<usize> for VecView<'_, T> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use crate::version::Version;
+
+	use super::Vec;
+
+	#[test]
+	fn view_get() {
+		let mut vec = Vec::new();
+		let empty = Version::new();
+		let mut version = empty;
+		for i in 0..3 {
+			version = vec.push_after(Box::new(i), version);
+		}
+		let popped = vec.pop_after(version);
+		let view = vec.view(version);
+		assert_eq!(view.len(), 3);
+		assert!(!view.is_empty());
+		for i in 0..3 {
+			assert_eq!(view.get(i), Some(&i));
+		}
+		assert_eq!(view.get(3), None);
+		assert_eq!(view.get(usize::MAX), None);
+		let view = vec.view(popped);
+		assert_eq!(view.len(), 2);
+		assert_eq!(view.get(2), None);
+		let view = vec.view(empty);
+		assert!(view.is_empty());
+		assert_eq!(view.get(0), None);
+	}
+
+	#[test]
+	fn set_after() {
+		let mut vec = Vec::new();
+		let mut version = Version::new();
+		for i in 0..3 {
+			version = vec.push_after(Box::new(i), version);
+		}
+		let set = vec.set_after(1, Box::new(10), version);
+		assert_eq!(vec.view(version)[1], 1);
+		assert_eq!(vec.view(set)[1], 10);
+		for i in [0, 2] {
+			assert_eq!(vec.view(version)[i], vec.view(set)[i]);
+		}
+		assert_eq!(vec.view(set).len(), 3);
+	}
+
+	#[test]
+	#[should_panic]
+	fn set_after_out_of_bounds() {
+		let mut vec = Vec::new();
+		let version = vec.push_after(Box::new(0), Version::new());
+		vec.set_after(1, Box::new(1), version);
+	}
+
+	#[test]
+	fn view_iter() {
+		let mut vec = Vec::new();
+		let mut versions = vec![Version::new()];
+		for i in 0..10 {
+			versions.push(vec.push_after(Box::new(i), *versions.last().unwrap()));
+		}
+		let popped = vec.pop_after(*versions.last().unwrap());
+		versions.push(vec.push_after(Box::new(20), popped));
+		for version in versions {
+			let view = vec.view(version);
+			let indexed: std::vec::Vec<_> = (0..view.len()).map(|i| view[i]).collect();
+			let iterated: std::vec::Vec<_> = view.iter().copied().collect();
+			assert_eq!(indexed, iterated);
+			let mut count = 0;
+			for value in &view {
+				assert_eq!(*value, view[count]);
+				count += 1;
+			}
+			assert_eq!(count, view.len());
+		}
+	}
+
+	#[test]
+	fn insert_remove_after() {
+		let mut vec = Vec::new();
+		let mut version = Version::new();
+		for i in 0..5 {
+			version = vec.push_after(Box::new(i), version);
+		}
+		let inserted = vec.insert_after(2, Box::new(10), version);
+		let removed = vec.remove_after(2, version);
+		let appended = vec.insert_after(5, Box::new(11), version);
+		let front = vec.remove_after(0, inserted);
+		let collect = |version| {
+			vec.view(version)
+				.iter()
+				.copied()
+				.collect::<std::vec::Vec<_>>()
+		};
+		assert_eq!(collect(version), [0, 1, 2, 3, 4]);
+		assert_eq!(collect(inserted), [0, 1, 10, 2, 3, 4]);
+		assert_eq!(collect(removed), [0, 1, 3, 4]);
+		assert_eq!(collect(appended), [0, 1, 2, 3, 4, 11]);
+		assert_eq!(collect(front), [1, 10, 2, 3, 4]);
+	}
+
+	#[test]
+	fn from_vec() {
+		let base = Version::new();
+		let (vec, version) = Vec::from_vec(vec![Box::new(1), Box::new(2), Box::new(3)], base);
+		assert_eq!(vec.view(version).len(), 3);
+		assert_eq!(
+			vec.view(version)
+				.iter()
+				.copied()
+				.collect::<std::vec::Vec<_>>(),
+			[1, 2, 3]
+		);
+		assert!(vec.view(base).is_empty());
+		let (vec, version) = Vec::from_slice(&[1, 2, 3], base);
+		assert_eq!(
+			vec.view(version)
+				.iter()
+				.copied()
+				.collect::<std::vec::Vec<_>>(),
+			[1, 2, 3]
+		);
+		let (vec, version) = Vec::<u64>::from_vec(vec![], base);
+		assert!(vec.view(version).is_empty());
+	}
+
+	#[test]
+	fn pop_value_after() {
+		let (mut vec, version) = Vec::from_slice(&[1, 2, 3], Version::new());
+		let (popped, value) = vec.pop_value_after(version).unwrap();
+		assert_eq!(*value, 3);
+		assert_eq!(vec.view(popped).len(), 2);
+		assert_eq!(vec.view(version).len(), 3);
+		let (popped, value) = vec.pop_value_after(popped).unwrap();
+		assert_eq!(*value, 2);
+		let (popped, value) = vec.pop_value_after(popped).unwrap();
+		assert_eq!(*value, 1);
+		assert!(vec.pop_value_after(popped).is_none());
+	}
+}
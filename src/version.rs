@@ -1,60 +1,183 @@
+use core::cmp::Ordering;
 use core::fmt;
+use core::hash::{Hash, Hasher};
 use core::ptr::NonNull;
+use core::sync::atomic::{
+	AtomicPtr, AtomicU64,
+	Ordering::{Acquire, Relaxed, Release},
+};
 
-use crate::util::alloc;
+use crate::util::{alloc, count_alloc, count_free, free};
 
-struct VersionList {
+struct VersionList<const B: usize> {
 	size: usize,
-	base: NonNull<VersionSuperNode>,
+	base: NonNull<VersionSuperNode<B>>,
+	// The number of `OwnedVersionList`s of the list. A list without owners is freed when its last
+	// version is deleted, and leaked otherwise. An owned list is freed by its last owner.
+	owners: usize,
+	// The arena the list and its versions are allocated in, if any. Nothing is freed for lists
+	// in an arena, as the arena frees everything at once.
+	arena: Option<NonNull<ArenaInner<B>>>,
 }
 
-struct VersionSuperNode {
-	parent: NonNull<VersionList>,
-	next: NonNull<VersionSuperNode>,
+struct VersionSuperNode<const B: usize> {
+	parent: NonNull<VersionList<B>>,
+	next: NonNull<VersionSuperNode<B>>,
 	size: usize,
-	value: u64,
-	list: NonNull<VersionNode>,
+	// The labels and the parents of the nodes are atomic, so `SyncVersion` can compare versions
+	// while another thread relabels them, see `sync::SyncVersion::try_cmp`.
+	value: AtomicU64,
+	list: NonNull<VersionNode<B>>,
 }
 
-struct VersionNode {
-	parent: NonNull<VersionSuperNode>,
-	next: Option<NonNull<VersionNode>>,
-	value: u64,
+struct VersionNode<const B: usize> {
+	parent: AtomicPtr<VersionSuperNode<B>>,
+	next: Option<NonNull<VersionNode<B>>>,
+	value: AtomicU64,
+	// The version tree structure of `Version`. The primary node of a version links to the
+	// secondary node of its parent, and the secondary node links to the primary node of the same
+	// version. None for the primary node of a root and for nodes of bare `PartialVersion`s.
+	link: Option<NonNull<VersionNode<B>>>,
 }
 
-unsafe fn node_parent(this: NonNull<VersionNode>) -> NonNull<VersionSuperNode> {
-	unsafe { this.as_ref().parent }
+/// Prints diagnostics about the version list to stderr if the `trace-version` feature is enabled.
+/// Expands to nothing otherwise, so the arguments are not even evaluated.
+macro_rules! trace {
+	($($arg:tt)*) => {
+		#[cfg(feature = "trace-version")]
+		eprintln!($($arg)*);
+	};
 }
 
-unsafe fn node_next(this: NonNull<VersionNode>) -> Option<NonNull<VersionNode>> {
+#[cfg(feature = "renumber-stats")]
+thread_local! {
+	static RENUMBER_COUNT: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+/// Counts relabeled nodes and super nodes if the `renumber-stats` feature is enabled.
+fn count_relabels(_count: usize) {
+	#[cfg(feature = "renumber-stats")]
+	RENUMBER_COUNT.with(|counter| counter.set(counter.get() + _count));
+}
+
+/// Returns the number of nodes and super nodes relabeled when splitting and renumbering super
+/// nodes on this thread since the last call to `reset_renumber_count`.
+#[cfg(feature = "renumber-stats")]
+pub fn renumber_count() -> usize {
+	RENUMBER_COUNT.with(|counter| counter.get())
+}
+
+/// Resets the relabeling counter of this thread.
+#[cfg(feature = "renumber-stats")]
+pub fn reset_renumber_count() {
+	RENUMBER_COUNT.with(|counter| counter.set(0));
+}
+
+/// Allocates a node of the list, in the arena of the list if it has one.
+unsafe fn alloc_node<const B: usize>(
+	list: NonNull<VersionList<B>>,
+	node: VersionNode<B>,
+) -> NonNull<VersionNode<B>> {
+	unsafe {
+		match list.as_ref().arena {
+			Some(mut arena) => arena.as_mut().nodes.alloc(node),
+			None => alloc(node),
+		}
+	}
+}
+
+/// Allocates a super node of the list, in the arena of the list if it has one.
+unsafe fn alloc_super_node<const B: usize>(
+	list: NonNull<VersionList<B>>,
+	super_node: VersionSuperNode<B>,
+) -> NonNull<VersionSuperNode<B>> {
+	unsafe {
+		match list.as_ref().arena {
+			Some(mut arena) => arena.as_mut().super_nodes.alloc(super_node),
+			None => alloc(super_node),
+		}
+	}
+}
+
+/// Frees a node, a super node or the list itself, unless the list is in an arena.
+unsafe fn free_in<const B: usize, T>(list: NonNull<VersionList<B>>, pointer: NonNull<T>) {
+	unsafe {
+		if list.as_ref().arena.is_none() {
+			free(pointer);
+		}
+	}
+}
+
+// The nodes, super nodes and lists are written through raw pointers rather than mutable
+// references, and the fields read when comparing versions are read through raw pointers as well.
+// `SyncVersion` compares versions while another thread inserts a version, and a reference to a
+// node counts as an access to all of its fields.
+
+unsafe fn node_parent<const B: usize>(
+	this: NonNull<VersionNode<B>>,
+) -> NonNull<VersionSuperNode<B>> {
+	// Acquire pairs with the release in `set_node_parent`, so the new super node is initialized
+	unsafe { NonNull::new_unchecked((*this.as_ptr()).parent.load(Acquire)) }
+}
+
+unsafe fn set_node_parent<const B: usize>(
+	this: NonNull<VersionNode<B>>,
+	parent: NonNull<VersionSuperNode<B>>,
+) {
+	unsafe { (*this.as_ptr()).parent.store(parent.as_ptr(), Release) }
+}
+
+unsafe fn node_next<const B: usize>(
+	this: NonNull<VersionNode<B>>,
+) -> Option<NonNull<VersionNode<B>>> {
 	unsafe { this.as_ref().next }
 }
 
-unsafe fn node_value(this: NonNull<VersionNode>) -> u64 {
-	unsafe { this.as_ref().value }
+unsafe fn node_value<const B: usize>(this: NonNull<VersionNode<B>>) -> u64 {
+	unsafe { (*this.as_ptr()).value.load(Relaxed) }
+}
+
+unsafe fn set_node_value<const B: usize>(this: NonNull<VersionNode<B>>, value: u64) {
+	unsafe { (*this.as_ptr()).value.store(value, Relaxed) }
+}
+
+unsafe fn node_link<const B: usize>(
+	this: NonNull<VersionNode<B>>,
+) -> Option<NonNull<VersionNode<B>>> {
+	unsafe { this.as_ref().link }
 }
 
-unsafe fn super_node_parent(this: NonNull<VersionSuperNode>) -> NonNull<VersionList> {
-	unsafe { this.as_ref().parent }
+unsafe fn super_node_parent<const B: usize>(
+	this: NonNull<VersionSuperNode<B>>,
+) -> NonNull<VersionList<B>> {
+	unsafe { (*this.as_ptr()).parent }
 }
 
-unsafe fn super_node_list(this: NonNull<VersionSuperNode>) -> NonNull<VersionNode> {
+unsafe fn super_node_list<const B: usize>(
+	this: NonNull<VersionSuperNode<B>>,
+) -> NonNull<VersionNode<B>> {
 	unsafe { this.as_ref().list }
 }
 
-unsafe fn super_node_next(this: NonNull<VersionSuperNode>) -> NonNull<VersionSuperNode> {
+unsafe fn super_node_next<const B: usize>(
+	this: NonNull<VersionSuperNode<B>>,
+) -> NonNull<VersionSuperNode<B>> {
 	unsafe { this.as_ref().next }
 }
 
-unsafe fn super_node_size(this: NonNull<VersionSuperNode>) -> usize {
+unsafe fn super_node_size<const B: usize>(this: NonNull<VersionSuperNode<B>>) -> usize {
 	unsafe { this.as_ref().size }
 }
 
-unsafe fn super_node_value(this: NonNull<VersionSuperNode>) -> u64 {
-	unsafe { this.as_ref().value }
+unsafe fn super_node_value<const B: usize>(this: NonNull<VersionSuperNode<B>>) -> u64 {
+	unsafe { (*this.as_ptr()).value.load(Relaxed) }
+}
+
+unsafe fn set_super_node_value<const B: usize>(this: NonNull<VersionSuperNode<B>>, value: u64) {
+	unsafe { (*this.as_ptr()).value.store(value, Relaxed) }
 }
 
-unsafe fn is_base(this: NonNull<VersionSuperNode>) -> bool {
+unsafe fn is_base<const B: usize>(this: NonNull<VersionSuperNode<B>>) -> bool {
 	unsafe {
 		let list = super_node_parent(this);
 		let base = list_base(list);
@@ -62,11 +185,56 @@ unsafe fn is_base(this: NonNull<VersionSuperNode>) -> bool {
 	}
 }
 
-unsafe fn list_base(this: NonNull<VersionList>) -> NonNull<VersionSuperNode> {
-	unsafe { this.as_ref().base }
+/// Returns the node after this node in the version list, which may be in the next super node.
+unsafe fn node_successor<const B: usize>(
+	this: NonNull<VersionNode<B>>,
+) -> Option<NonNull<VersionNode<B>>> {
+	unsafe {
+		node_next(this).or_else(|| {
+			let next = super_node_next(node_parent(this));
+			(!is_base(next)).then(|| super_node_list(next))
+		})
+	}
+}
+
+/// Returns the node before this node in the version list, which may be in the previous super node.
+/// The nodes only link forwards, so this scans the super node, and the super nodes if this is the
+/// first node of its super node.
+unsafe fn node_predecessor<const B: usize>(
+	this: NonNull<VersionNode<B>>,
+) -> Option<NonNull<VersionNode<B>>> {
+	unsafe {
+		let parent = node_parent(this);
+		let mut node = super_node_list(parent);
+		if node == this {
+			if is_base(parent) {
+				return None;
+			}
+			let mut prev = super_node_next(parent);
+			while super_node_next(prev) != parent {
+				prev = super_node_next(prev);
+			}
+			node = super_node_list(prev);
+			while let Some(next) = node_next(node) {
+				node = next;
+			}
+			return Some(node);
+		}
+		while node_next(node) != Some(this) {
+			node = node_next(node).expect("the version to be in the list of its parent");
+		}
+		Some(node)
+	}
+}
+
+unsafe fn list_base<const B: usize>(this: NonNull<VersionList<B>>) -> NonNull<VersionSuperNode<B>> {
+	unsafe { (*this.as_ptr()).base }
 }
 
-unsafe fn split_super(mut this: NonNull<VersionSuperNode>) {
+unsafe fn split_super<const B: usize>(this: NonNull<VersionSuperNode<B>>) {
+	// The B nodes of the super node are relabeled.
+	count_relabels(B);
+	trace!("splitting super node");
 	unsafe {
 		let next = super_node_next(this);
 		let this_value = super_node_value(this);
@@ -78,57 +246,168 @@ unsafe fn split_super(mut this: NonNull<VersionSuperNode>) {
 				.div_ceil(2),
 		);
 		let parent = super_node_parent(this);
-		let mut new_node = alloc(VersionSuperNode {
+		let new_node = alloc_super_node(
 			parent,
-			next,
-			size: 32,
-			value,
-			list: NonNull::dangling(),
-		});
-		this.as_mut().next = new_node;
-		this.as_mut().size = 32;
+			VersionSuperNode {
+				parent,
+				next,
+				size: B - B / 2,
+				value: AtomicU64::new(value),
+				list: NonNull::dangling(),
+			},
+		);
+		(*this.as_ptr()).next = new_node;
+		(*this.as_ptr()).size = B / 2;
 		let list = super_node_list(this);
 		if value == this_value {
 			renumber(this);
 		}
-		new_node.as_mut().list = split(list, 0, new_node);
+		(*new_node.as_ptr()).list = split(list, 0, new_node);
+	}
+}
+
+/// Removes the super node from the list of super nodes and frees it. If it is the only super node
+/// the version list is freed as well, unless the list has owners.
+unsafe fn remove_super<const B: usize>(this: NonNull<VersionSuperNode<B>>) {
+	unsafe {
+		let list = super_node_parent(this);
+		let next = super_node_next(this);
+		if next == this {
+			free_in(list, this);
+			// An owned list is freed by its last owner.
+			if list.as_ref().owners == 0 {
+				free_in(list, list);
+			}
+			return;
+		}
+		let mut prev = next;
+		while super_node_next(prev) != this {
+			prev = super_node_next(prev);
+		}
+		(*prev.as_ptr()).next = next;
+		if list_base(list) == this {
+			(*list.as_ptr()).base = next;
+		}
+		free_in(list, this);
+	}
+}
+
+/// Merges the super node with the following super node, or with the preceding super node if it is
+/// the last super node. Nothing is merged if it is the only super node or if the merged super node
+/// would be full.
+unsafe fn merge_small<const B: usize>(this: NonNull<VersionSuperNode<B>>) {
+	unsafe {
+		let next = super_node_next(this);
+		if next == this {
+			return;
+		}
+		let (first, second) = if is_base(next) {
+			let mut prev = next;
+			while super_node_next(prev) != this {
+				prev = super_node_next(prev);
+			}
+			(prev, this)
+		} else {
+			(this, next)
+		};
+		merge(first, second);
+	}
+}
+
+/// Moves the nodes of the second super node to the end of the first super node and removes the
+/// second super node, if the merged super node would not be full. The second super node must
+/// follow the first super node and must not be the base super node.
+unsafe fn merge<const B: usize>(
+	first: NonNull<VersionSuperNode<B>>,
+	second: NonNull<VersionSuperNode<B>>,
+) {
+	unsafe {
+		let size = super_node_size(first) + super_node_size(second);
+		if size >= B {
+			return;
+		}
+		trace!("merging super nodes into {} nodes", size);
+		let mut last = super_node_list(first);
+		while let Some(next) = node_next(last) {
+			last = next;
+		}
+		let mut node = Some(super_node_list(second));
+		(*last.as_ptr()).next = node;
+		while let Some(current) = node {
+			set_node_parent(current, first);
+			node = node_next(current);
+		}
+		(*first.as_ptr()).size = size;
+		remove_super(second);
+		relabel_nodes(first);
 	}
 }
 
-unsafe fn renumber(this: NonNull<VersionSuperNode>) {
+/// Relabels this super node and the following super nodes such that there is room for a new super
+/// node after this super node. The scan is widened until the room for the relabeled super nodes is
+/// at least the square of their count, so the new labels are strictly increasing with a gap of at
+/// least one.
+unsafe fn renumber<const B: usize>(this: NonNull<VersionSuperNode<B>>) {
 	unsafe {
-		let mut j = 1;
 		let this_value = super_node_value(this);
+		// The room from this super node to the given super node. If the scan wraps around to this
+		// super node the whole label space is available.
+		let room = |next: NonNull<VersionSuperNode<B>>| {
+			if next == this {
+				1 << 64
+			} else {
+				u128::from(super_node_value(next).wrapping_sub(this_value))
+			}
+		};
+		let mut j: u128 = 1;
 		let mut next = super_node_next(this);
-		let mut current_value = super_node_value(next);
-		while current_value.wrapping_sub(this_value) < j * j {
+		while room(next) < j * j {
 			next = super_node_next(next);
-			current_value = super_node_value(next);
 			j += 1;
 		}
-		dbg!(j);
-		let interval = current_value.wrapping_sub(this_value) / j;
+		count_relabels(j as usize);
+		trace!("renumbering {} super nodes", j);
+		// The interval is at least j, and the largest label is below the label of next
+		let interval = room(next) / j;
 		let mut current = this;
 		for i in 0..j {
-			current.as_mut().value = this_value.wrapping_add(interval * i);
+			set_super_node_value(current, this_value.wrapping_add((interval * i) as u64));
 			current = super_node_next(current);
 		}
 	}
 }
 
-unsafe fn split(
-	mut this: NonNull<VersionNode>,
+/// Spreads the labels of the nodes of the super node evenly. This is needed when there is no room
+/// between two nodes, which can happen before the super node is full if versions were deleted.
+unsafe fn relabel_nodes<const B: usize>(this: NonNull<VersionSuperNode<B>>) {
+	const VALUE: u64 = 1 << 32;
+	count_relabels(unsafe { super_node_size(this) });
+	trace!("relabeling nodes of super node");
+	unsafe {
+		// Start at one label in, such that there is room before the first node
+		let mut node = Some(super_node_list(this));
+		let mut index = 1;
+		while let Some(current) = node {
+			set_node_value(current, VALUE * index);
+			node = node_next(current);
+			index += 1;
+		}
+	}
+}
+
+unsafe fn split<const B: usize>(
+	this: NonNull<VersionNode<B>>,
 	index: u64,
-	new_parent: NonNull<VersionSuperNode>,
-) -> NonNull<VersionNode> {
+	new_parent: NonNull<VersionSuperNode<B>>,
+) -> NonNull<VersionNode<B>> {
 	const VALUE: u64 = 1 << 32;
 	unsafe {
-		this.as_mut().value = VALUE * index;
-		let next = node_next(this).expect("the length of the linked list to be 64");
+		set_node_value(this, VALUE * index);
+		let next = node_next(this).expect("the length of the linked list to be B");
 
-		if index == 31 {
+		if index + 1 == (B / 2) as u64 {
 			split_tail(next, 0, new_parent);
-			this.as_mut().next = None;
+			(*this.as_ptr()).next = None;
 			next
 		} else {
 			split(next, index + 1, new_parent)
@@ -136,18 +415,18 @@ unsafe fn split(
 	}
 }
 
-unsafe fn split_tail(
-	mut this: NonNull<VersionNode>,
+unsafe fn split_tail<const B: usize>(
+	this: NonNull<VersionNode<B>>,
 	index: u64,
-	new_parent: NonNull<VersionSuperNode>,
+	new_parent: NonNull<VersionSuperNode<B>>,
 ) {
 	const VALUE: u64 = 1 << 32;
 	unsafe {
-		this.as_mut().value = VALUE * index;
-		this.as_mut().parent = new_parent;
+		set_node_value(this, VALUE * index);
+		set_node_parent(this, new_parent);
 
-		if index < 31 {
-			let next = node_next(this).expect("the length of the linked list to be 64");
+		if index + 1 < (B - B / 2) as u64 {
+			let next = node_next(this).expect("the length of the linked list to be B");
 			split_tail(next, index + 1, new_parent);
 		}
 	}
@@ -157,60 +436,188 @@ unsafe fn split_tail(
 /// versions from other version lists is meaningless. The type uses pointers internally with
 /// interior mutability therefore the debug print output can change when new versions are added to
 /// the list.
+///
+/// Versions do not own the version list, as they are `Copy`. If the list is owned by an
+/// `OwnedVersionList` it is freed with its last owner. Otherwise it is freed when its last version
+/// is deleted, and leaked if its versions are not all deleted.
 #[derive(Clone, Copy)]
-pub struct Version {
-	pub primary: PartialVersion,
-	pub secondary: PartialVersion,
+pub struct Version<const B: usize = 64> {
+	pub primary: PartialVersion<B>,
+	pub secondary: PartialVersion<B>,
 }
 
-impl Default for Version {
+impl<const B: usize> Default for Version<B> {
 	fn default() -> Self {
-		Self::new()
+		Self::with_block_size()
 	}
 }
 
 impl Version {
 	pub fn new() -> Version {
-		let primary = PartialVersion::new();
+		Version::with_block_size()
+	}
+}
+
+impl<const B: usize> Version<B> {
+	/// Creates a new version and the associated version list, which splits its super nodes when
+	/// they reach B versions. See `PartialVersion::with_block_size`.
+	pub fn with_block_size() -> Version<B> {
+		let primary = PartialVersion::with_block_size();
+		Version::new_child(primary, None)
+	}
+
+	/// Creates the version with the given primary version and parent by inserting its secondary
+	/// version directly after the primary version.
+	fn new_child(primary: PartialVersion<B>, parent: Option<Version<B>>) -> Version<B> {
 		let secondary = primary.insert_after();
+		// SAFETY: The nodes were just allocated and no references to them exist
+		unsafe {
+			(*primary.node.as_ptr()).link = parent.map(|parent| parent.secondary.node);
+			(*secondary.node.as_ptr()).link = Some(primary.node);
+		}
 		Version { primary, secondary }
 	}
 
-	pub fn insert_after(self) -> Version {
+	/// Creates a new version and the associated version list in the arena, see
+	/// `PartialVersion::new_in`.
+	///
+	/// # Safety
+	/// No version of the list may be used after the arena is dropped or reset.
+	pub unsafe fn new_in(arena: &VersionArena<B>) -> Version<B> {
+		let primary = unsafe { PartialVersion::new_in(arena) };
+		Version::new_child(primary, None)
+	}
+
+	pub fn insert_after(self) -> Version<B> {
 		let primary = self.primary.insert_after();
-		let secondary = primary.insert_after();
-		Version { primary, secondary }
+		Version::new_child(primary, Some(self))
+	}
+
+	/// Inserts a new version directly before this version, such that it is a sibling of this
+	/// version created before it.
+	pub fn insert_before(self) -> Version<B> {
+		let primary = self.primary.insert_before();
+		Version::new_child(primary, self.parent())
+	}
+
+	/// The version this version was inserted after, or None if this is a root. Versions created
+	/// with `insert_before` have the same parent as the version they were inserted before.
+	pub fn parent(self) -> Option<Version<B>> {
+		unsafe {
+			node_link(self.primary.node).map(|secondary| Version {
+				primary: PartialVersion {
+					node: node_link(secondary).expect("the secondary version to link its primary"),
+				},
+				secondary: PartialVersion { node: secondary },
+			})
+		}
+	}
+
+	/// Returns true if this version is an ancestor of the other version, that is if the other
+	/// version descends from this version through `insert_after`. A version is an ancestor of
+	/// itself. Versions from different version lists are never ancestors of each other.
+	pub fn is_ancestor_of(&self, other: Version<B>) -> bool {
+		// The primary and secondary versions of the descendants of a version are between the
+		// primary and secondary version of the version
+		self.list_id() == other.list_id()
+			&& self.primary <= other.primary
+			&& other.secondary <= self.secondary
+	}
+
+	/// The lowest common ancestor of the versions, that is the version closest to the versions
+	/// which is an ancestor of both. Returns None if the versions have no common ancestor, as if
+	/// they are from different version lists or descend from different roots.
+	pub fn lca(a: Version<B>, b: Version<B>) -> Option<Version<B>> {
+		let mut ancestor = Some(a);
+		while let Some(version) = ancestor {
+			if version.is_ancestor_of(b) {
+				return Some(version);
+			}
+			ancestor = version.parent();
+		}
+		None
+	}
+
+	/// Deletes the primary and secondary version of this version, see `PartialVersion::delete`.
+	///
+	/// # Safety
+	/// This version and all copies of it must not be used after this. The parent, and thereby
+	/// the lowest common ancestor, of the descendants of this version must not be queried after
+	/// this.
+	pub unsafe fn delete(self) {
+		unsafe {
+			self.primary.delete();
+			self.secondary.delete();
+		}
+	}
+
+	/// Compares the versions if they are from the same version list. Returns None if they are
+	/// from different version lists.
+	pub fn try_cmp(&self, other: &Self) -> Option<Ordering> {
+		self.primary.try_cmp(&other.primary)
+	}
+
+	/// The identity of the version list this version belongs to, see `PartialVersion::list_id`.
+	pub fn list_id(self) -> ListId<B> {
+		self.primary.list_id()
+	}
+
+	/// Returns true if no version has been inserted after this version, that is if the version
+	/// has no descendants.
+	pub fn is_leaf(self) -> bool {
+		unsafe { node_successor(self.primary.node) == Some(self.secondary.node) }
+	}
+
+	/// Returns true if the secondary version is the secondary version of the version with the
+	/// primary version. Bare `PartialVersion`s are never secondary versions.
+	pub(crate) fn is_secondary_of(
+		secondary: PartialVersion<B>,
+		primary: PartialVersion<B>,
+	) -> bool {
+		unsafe { node_link(secondary.node) == Some(primary.node) }
 	}
 }
 
-impl PartialEq for Version {
+impl<const B: usize> PartialEq for Version<B> {
 	fn eq(&self, other: &Self) -> bool {
 		self.primary.eq(&other.primary)
 	}
 }
 
-impl Eq for Version {}
+impl<const B: usize> Eq for Version<B> {}
+
+/// Hashes the primary version, see the `Hash` implementation of `PartialVersion`.
+impl<const B: usize> Hash for Version<B> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.primary.hash(state);
+	}
+}
 
-impl PartialOrd for Version {
-	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl<const B: usize> PartialOrd for Version<B> {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
 		Some(self.cmp(other))
 	}
 }
 
-impl Ord for Version {
-	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+impl<const B: usize> Ord for Version<B> {
+	fn cmp(&self, other: &Self) -> Ordering {
 		self.primary.cmp(&other.primary)
 	}
 }
 
+/// Opaque identity of a version list. Two versions have the same list id exactly when they are
+/// from the same version list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ListId<const B: usize = 64>(NonNull<VersionList<B>>);
+
 #[derive(Clone, Copy)]
-pub struct PartialVersion {
-	node: NonNull<VersionNode>,
+pub struct PartialVersion<const B: usize = 64> {
+	node: NonNull<VersionNode<B>>,
 }
 
-impl Default for PartialVersion {
+impl<const B: usize> Default for PartialVersion<B> {
 	fn default() -> Self {
-		Self::new()
+		Self::with_block_size()
 	}
 }
 
@@ -218,62 +625,250 @@ impl PartialVersion {
 	/// Creates a new version and the associatied version list. Comparing this with version
 	/// from other version lists is meaningless.
 	pub fn new() -> PartialVersion {
-		let mut node = alloc(VersionNode {
-			parent: NonNull::dangling(),
-			next: None,
-			value: 0,
-		});
-		let mut super_node = alloc(VersionSuperNode {
-			parent: NonNull::dangling(),
-			next: NonNull::dangling(),
-			size: 1,
-			value: 0,
-			list: node,
-		});
-		let list = alloc(VersionList {
-			size: 1,
-			base: super_node,
-		});
+		PartialVersion::with_block_size()
+	}
+}
+
+impl<const B: usize> PartialVersion<B> {
+	/// Creates a new version and the associated version list, which splits its super nodes when
+	/// they reach B versions. A smaller B makes relabeling the nodes of a super node cheaper, and
+	/// a larger B makes fewer super nodes which need to be relabeled. B must be at least 2.
+	pub fn with_block_size() -> PartialVersion<B> {
+		PartialVersion::new_list(None)
+	}
+
+	/// Creates a new version and the associated version list in the arena. Every version of the
+	/// list is allocated in the arena, and deleting versions does not free them.
+	///
+	/// # Safety
+	/// No version of the list may be used after the arena is dropped or reset.
+	pub unsafe fn new_in(arena: &VersionArena<B>) -> PartialVersion<B> {
+		PartialVersion::new_list(Some(arena.inner))
+	}
 
-		// SAFETY: No other references exist while we use the references
-		unsafe { node.as_mut() }.parent = super_node;
-		unsafe { super_node.as_mut() }.parent = list;
-		unsafe { super_node.as_mut() }.next = super_node;
+	fn new_list(arena: Option<NonNull<ArenaInner<B>>>) -> PartialVersion<B> {
+		const { assert!(B >= 2, "super nodes must have room for at least 2 versions") };
+		let list = VersionList {
+			size: 1,
+			base: NonNull::dangling(),
+			owners: 0,
+			arena,
+		};
+		let list = match arena {
+			Some(mut arena) => unsafe { arena.as_mut() }.lists.alloc(list),
+			None => alloc(list),
+		};
+		// SAFETY: The list was just allocated, and no other references exist while we use the
+		// references
+		unsafe {
+			let super_node = alloc_super_node(
+				list,
+				VersionSuperNode {
+					parent: list,
+					next: NonNull::dangling(),
+					size: 1,
+					value: AtomicU64::new(0),
+					list: NonNull::dangling(),
+				},
+			);
+			let node = alloc_node(
+				list,
+				VersionNode {
+					parent: AtomicPtr::new(super_node.as_ptr()),
+					next: None,
+					value: AtomicU64::new(0),
+					link: None,
+				},
+			);
+			(*super_node.as_ptr()).list = node;
+			(*super_node.as_ptr()).next = super_node;
+			(*list.as_ptr()).base = super_node;
 
-		PartialVersion { node }
+			PartialVersion { node }
+		}
 	}
 
 	/// Inserts a new version directly after this version and returns it.
-	fn insert_after(mut self) -> PartialVersion {
+	pub fn insert_after(self) -> PartialVersion<B> {
 		unsafe {
 			let next = node_next(self.node);
-			let prev_value = node_value(self.node);
-			let next_value = next.map(|next| node_value(next)).unwrap_or(u64::MAX);
+			let mut prev_value = node_value(self.node);
+			let mut next_value = next.map(|next| node_value(next)).unwrap_or(u64::MAX);
+			if next_value - prev_value < 2 {
+				relabel_nodes(node_parent(self.node));
+				prev_value = node_value(self.node);
+				next_value = next.map(|next| node_value(next)).unwrap_or(u64::MAX);
+			}
 			// It does matter if we use div_ceil or div_floor in the general case.
 			// however we can approximate the out of bounds value as u64::MAX with
 			// div_ceil and still get the correct value, which means that we can have
-			// list of size 64 instead of 63.
+			// list of size B instead of B - 1.
 			let value = prev_value + (next_value - prev_value).div_ceil(2);
-			let mut parent = node_parent(self.node);
-			let new_version = alloc(VersionNode {
-				parent,
-				next,
-				value,
-			});
-			self.node.as_mut().next = Some(new_version);
+			let parent = node_parent(self.node);
+			let new_version = alloc_node(
+				super_node_parent(parent),
+				VersionNode {
+					parent: AtomicPtr::new(parent.as_ptr()),
+					next,
+					value: AtomicU64::new(value),
+					link: None,
+				},
+			);
+			(*self.node.as_ptr()).next = Some(new_version);
+
+			(*parent.as_ptr()).size += 1;
+			if super_node_size(parent) == B {
+				split_super(parent);
+			}
+
+			let list = super_node_parent(parent);
+			(*list.as_ptr()).size += 1;
+
+			PartialVersion { node: new_version }
+		}
+	}
+
+	/// Deletes this version from the version list and frees it. The order of the remaining
+	/// versions is unaffected. If this is the last version of the list the list is freed, unless
+	/// it has owners, which free it instead. If the super node of the version has less than B / 4
+	/// versions left it is merged with a neighbouring super node, such that the super nodes do not
+	/// stay small when many versions are deleted.
+	///
+	/// # Safety
+	/// This version and all copies of it must not be used after this, including copies stored in
+	/// data structures such as `PersistentCell`.
+	pub unsafe fn delete(self) {
+		unsafe {
+			let parent = node_parent(self.node);
+			let list = super_node_parent(parent);
+			(*parent.as_ptr()).size -= 1;
+			(*list.as_ptr()).size -= 1;
+			let next = node_next(self.node);
+			let head = super_node_list(parent);
+			if head == self.node {
+				match next {
+					Some(next) => (*parent.as_ptr()).list = next,
+					None => {
+						free_in(list, self.node);
+						remove_super(parent);
+						return;
+					}
+				}
+			} else {
+				let mut prev = head;
+				while node_next(prev) != Some(self.node) {
+					prev = node_next(prev).expect("the version to be in the list of its parent");
+				}
+				(*prev.as_ptr()).next = next;
+			}
+			free_in(list, self.node);
+			if super_node_size(parent) < B / 4 {
+				merge_small(parent);
+			}
+		}
+	}
+
+	/// Compares the versions if they are from the same version list. Returns None if they are
+	/// from different version lists, in which case comparing them is meaningless.
+	pub fn try_cmp(&self, other: &Self) -> Option<Ordering> {
+		(self.list_id() == other.list_id()).then(|| self.cmp(other))
+	}
+
+	/// The identity of the version list this version belongs to. Versions can only be compared
+	/// if they have the same list id.
+	pub fn list_id(self) -> ListId<B> {
+		ListId(self.list())
+	}
+
+	/// The version list this version belongs to. This is the identity of the list, as a version
+	/// never moves to another list.
+	fn list(self) -> NonNull<VersionList<B>> {
+		unsafe { super_node_parent(node_parent(self.node)) }
+	}
+
+	/// Inserts a new version directly before this version and returns it. If this is the first
+	/// version of its super node, the new version becomes the first version of the super node,
+	/// which relabels the nodes of the super node if there is no room below this version.
+	pub fn insert_before(self) -> PartialVersion<B> {
+		unsafe {
+			let parent = node_parent(self.node);
+			let head = super_node_list(parent);
+			if head != self.node {
+				let mut prev = head;
+				while node_next(prev) != Some(self.node) {
+					prev = node_next(prev).expect("the version to be in the list of its parent");
+				}
+				return PartialVersion { node: prev }.insert_after();
+			}
+			// The version is the first of its super node, so the new version becomes the first
+			if node_value(self.node) == 0 {
+				relabel_nodes(parent);
+			}
+			let new_version = alloc_node(
+				super_node_parent(parent),
+				VersionNode {
+					parent: AtomicPtr::new(parent.as_ptr()),
+					next: Some(self.node),
+					value: AtomicU64::new(node_value(self.node) / 2),
+					link: None,
+				},
+			);
+			(*parent.as_ptr()).list = new_version;
 
-			parent.as_mut().size += 1;
-			if super_node_size(parent) == 64 {
+			(*parent.as_ptr()).size += 1;
+			if super_node_size(parent) == B {
 				split_super(parent);
 			}
 
-			let mut list = super_node_parent(parent);
-			list.as_mut().size += 1;
+			let list = super_node_parent(parent);
+			(*list.as_ptr()).size += 1;
 
 			PartialVersion { node: new_version }
 		}
 	}
 
+	/// Returns the version directly after this version, or None if this is the last version.
+	pub fn successor(self) -> Option<PartialVersion<B>> {
+		unsafe { node_successor(self.node) }.map(|node| PartialVersion { node })
+	}
+
+	/// Returns the version directly before this version, or None if this is the first version.
+	/// Versions only link to the following version, so this runs in time linear in the size of a
+	/// super node, and in the number of super nodes if this is the first version of its super
+	/// node.
+	pub fn predecessor(self) -> Option<PartialVersion<B>> {
+		unsafe { node_predecessor(self.node) }.map(|node| PartialVersion { node })
+	}
+
+	/// Returns an iterator over every version of the version list of this version, in ascending
+	/// order, starting from the first version of the list. See `iter_versions`.
+	pub fn iter_from_start(self) -> impl Iterator<Item = PartialVersion<B>> {
+		iter_versions(self)
+	}
+
+	/// The position of this version in the version list as a single number, such that versions
+	/// compare the same as their ranks. The rank of a version changes when the list is relabeled,
+	/// which may happen on any insertion into the list, so ranks are only comparable as long as
+	/// the list is not modified.
+	pub fn rank(self) -> u128 {
+		self.order_key_u128()
+	}
+
+	/// The key which versions are ordered by, as the label of the super node relative to the
+	/// first super node and the label of the node. Versions compare the same as their keys. The
+	/// key is only valid until the next insertion into the version list, as any insertion may
+	/// relabel the versions, so keys must not be stored across insertions.
+	pub fn order_key(&self) -> (u64, u64) {
+		self.ordering_values()
+	}
+
+	/// The order key packed into a single number, with the super node label in the upper 64 bits.
+	/// Only valid until the next insertion into the version list, see `order_key`.
+	pub fn order_key_u128(&self) -> u128 {
+		let (major, minor) = self.ordering_values();
+		((major as u128) << 64) | minor as u128
+	}
+
 	fn ordering_values(self) -> (u64, u64) {
 		unsafe {
 			let minor = node_value(self.node);
@@ -286,7 +881,220 @@ impl PartialVersion {
 	}
 }
 
-impl fmt::Debug for PartialVersion {
+/// Returns an iterator over every version of the version list the version belongs to, in
+/// ascending order.
+pub fn iter_versions<const B: usize>(
+	version: PartialVersion<B>,
+) -> impl Iterator<Item = PartialVersion<B>> {
+	let first = unsafe { super_node_list(list_base(version.list())) };
+	core::iter::successors(Some(first), |&node| unsafe { node_successor(node) })
+		.map(|node| PartialVersion { node })
+}
+
+/// Returns the number of versions strictly between the two versions in the version list. Whole
+/// super nodes between the versions are skipped using their sizes. Returns None if a is greater
+/// than b or if they are from different version lists.
+pub fn count_between<const B: usize>(a: PartialVersion<B>, b: PartialVersion<B>) -> Option<usize> {
+	if a.try_cmp(&b)?.is_gt() {
+		return None;
+	}
+	if a == b {
+		return Some(0);
+	}
+	unsafe {
+		let a_parent = node_parent(a.node);
+		let b_parent = node_parent(b.node);
+		let mut count = 0;
+		let mut node = node_next(a.node);
+		while node != Some(b.node) {
+			let Some(current) = node else {
+				break;
+			};
+			count += 1;
+			node = node_next(current);
+		}
+		if a_parent == b_parent {
+			return Some(count);
+		}
+		let mut super_node = super_node_next(a_parent);
+		while super_node != b_parent {
+			count += super_node_size(super_node);
+			super_node = super_node_next(super_node);
+		}
+		let mut node = super_node_list(b_parent);
+		while node != b.node {
+			count += 1;
+			node = node_next(node).expect("the version to be in the list of its parent");
+		}
+		Some(count)
+	}
+}
+
+/// Arena which allocates version lists and their versions in bulk. Creating a version list and
+/// inserting versions into it allocates in chunks, rather than a heap allocation per version,
+/// and everything is freed at once when the arena is dropped or reset. This is faster for
+/// algorithms which create and discard many version lists.
+pub struct VersionArena<const B: usize = 64> {
+	inner: NonNull<ArenaInner<B>>,
+}
+
+struct ArenaInner<const B: usize> {
+	lists: Chunks<VersionList<B>>,
+	super_nodes: Chunks<VersionSuperNode<B>>,
+	nodes: Chunks<VersionNode<B>>,
+}
+
+/// Chunks of values which never move. A chunk is never pushed to beyond its capacity, so the
+/// values are never moved by a reallocation.
+struct Chunks<T> {
+	chunks: Vec<Vec<T>>,
+}
+
+impl<T> Chunks<T> {
+	fn alloc(&mut self, value: T) -> NonNull<T> {
+		let capacity = match self.chunks.last() {
+			Some(chunk) if chunk.len() < chunk.capacity() => None,
+			Some(chunk) => Some(chunk.capacity() * 2),
+			None => Some(16),
+		};
+		if let Some(capacity) = capacity {
+			count_alloc();
+			self.chunks.push(Vec::with_capacity(capacity));
+		}
+		let chunk = self
+			.chunks
+			.last_mut()
+			.expect("a chunk was just pushed if there were none");
+		let index = chunk.len();
+		chunk.push(value);
+		// The pointer is taken without creating a reference to the chunk, such that it is not
+		// invalidated by later pushes to the chunk.
+		unsafe { NonNull::new_unchecked(chunk.as_mut_ptr().add(index)) }
+	}
+
+	fn clear(&mut self) {
+		for _ in self.chunks.drain(..) {
+			count_free();
+		}
+	}
+}
+
+impl Default for VersionArena {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl VersionArena {
+	pub fn new() -> VersionArena {
+		VersionArena::with_block_size()
+	}
+}
+
+impl<const B: usize> VersionArena<B> {
+	/// Creates an arena for version lists with the given super node size, see
+	/// `PartialVersion::with_block_size`.
+	pub fn with_block_size() -> VersionArena<B> {
+		VersionArena {
+			inner: alloc(ArenaInner {
+				lists: Chunks { chunks: Vec::new() },
+				super_nodes: Chunks { chunks: Vec::new() },
+				nodes: Chunks { chunks: Vec::new() },
+			}),
+		}
+	}
+
+	/// Frees every version list of the arena, keeping the arena for new version lists.
+	///
+	/// # Safety
+	/// No version of a list in the arena may be used after this.
+	pub unsafe fn reset(&mut self) {
+		let inner = unsafe { self.inner.as_mut() };
+		inner.lists.clear();
+		inner.super_nodes.clear();
+		inner.nodes.clear();
+	}
+}
+
+impl<const B: usize> Drop for VersionArena<B> {
+	fn drop(&mut self) {
+		unsafe {
+			self.reset();
+			free(self.inner);
+		}
+	}
+}
+
+/// Owner of a version list. The version list and all of its versions are freed when the last
+/// owner of the list is dropped. Version lists without owners are only freed when their last
+/// version is deleted, see `PartialVersion::delete`.
+pub struct OwnedVersionList<const B: usize = 64> {
+	list: NonNull<VersionList<B>>,
+}
+
+impl<const B: usize> OwnedVersionList<B> {
+	/// Creates an owner of the version list the version belongs to.
+	///
+	/// # Safety
+	/// No version of the list may be used after the last owner of the list is dropped. This
+	/// includes versions stored in data structures such as `PersistentCell`, which therefore must
+	/// be dropped before the last owner.
+	pub unsafe fn new(version: PartialVersion<B>) -> OwnedVersionList<B> {
+		unsafe {
+			let list = super_node_parent(node_parent(version.node));
+			(*list.as_ptr()).owners += 1;
+			OwnedVersionList { list }
+		}
+	}
+
+	/// Returns the number of versions in the version list. Deleted versions are not counted.
+	pub fn version_count(&self) -> usize {
+		unsafe { self.list.as_ref().size }
+	}
+}
+
+impl<const B: usize> Clone for OwnedVersionList<B> {
+	fn clone(&self) -> Self {
+		let mut list = self.list;
+		unsafe { list.as_mut() }.owners += 1;
+		OwnedVersionList { list }
+	}
+}
+
+impl<const B: usize> Drop for OwnedVersionList<B> {
+	fn drop(&mut self) {
+		unsafe {
+			(*self.list.as_ptr()).owners -= 1;
+			// Lists in an arena are freed with the arena
+			if self.list.as_ref().owners > 0 || self.list.as_ref().arena.is_some() {
+				return;
+			}
+			// If every version has been deleted there are no super nodes left
+			if self.list.as_ref().size == 0 {
+				free(self.list);
+				return;
+			}
+			let base = list_base(self.list);
+			let mut super_node = base;
+			loop {
+				let next = super_node_next(super_node);
+				let mut node = Some(super_node_list(super_node));
+				while let Some(current) = node {
+					node = node_next(current);
+					free(current);
+				}
+				free(super_node);
+				if next == base {
+					break;
+				}
+				super_node = next;
+			}
+			free(self.list);
+		}
+	}
+}
+
+impl<const B: usize> fmt::Debug for PartialVersion<B> {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		let (major, minor) = self.ordering_values();
 
@@ -297,29 +1105,48 @@ impl fmt::Debug for PartialVersion {
 	}
 }
 
-impl PartialEq for PartialVersion {
+/// Versions are equal if they are the same version, so versions from different version lists are
+/// not equal.
+impl<const B: usize> PartialEq for PartialVersion<B> {
 	fn eq(&self, other: &Self) -> bool {
-		self.cmp(other).is_eq()
+		self.node == other.node
 	}
 }
 
-impl Eq for PartialVersion {}
+impl<const B: usize> Eq for PartialVersion<B> {}
 
-impl PartialOrd for PartialVersion {
-	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+/// Hashes the identity of the version rather than its position in the list, as the position
+/// changes when versions are relabeled. This is consistent with `PartialEq`.
+impl<const B: usize> Hash for PartialVersion<B> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.node.hash(state);
+	}
+}
+
+impl<const B: usize> PartialOrd for PartialVersion<B> {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
 		Some(self.cmp(other))
 	}
 }
 
-impl Ord for PartialVersion {
-	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+/// Comparing versions from different version lists is meaningless, and panics in debug builds.
+/// Use `try_cmp` if the versions may be from different version lists.
+impl<const B: usize> Ord for PartialVersion<B> {
+	fn cmp(&self, other: &Self) -> Ordering {
+		debug_assert!(
+			self.list() == other.list(),
+			"compared versions from different version lists"
+		);
 		self.ordering_values().cmp(&other.ordering_values())
 	}
 }
 
 #[cfg(test)]
 mod test {
-	use super::PartialVersion;
+	use core::cmp::Ordering;
+	use std::collections::HashSet;
+
+	use super::{OwnedVersionList, PartialVersion, Version, VersionArena};
 
 	#[test]
 	fn version_test() {
@@ -328,6 +1155,9 @@ mod test {
 			let i = fastrand::usize(..version_list.len());
 			let new_version = version_list[i].insert_after();
 			version_list.insert(i + 1, new_version);
+			if version_list.len() % 1000 == 0 {
+				validate(new_version);
+			}
 		}
 		for k in 0..version_list.len() {
 			assert_eq!(version_list[k], version_list[k]);
@@ -342,9 +1172,13 @@ mod test {
 	fn adversarial() {
 		let mut version_list = vec![];
 		let version = PartialVersion::new();
-		for _ in 0..100000 {
+		for i in 0..100000 {
 			version_list.push(version.insert_after());
+			if i % 1000 == 0 {
+				validate(version);
+			}
 		}
+		validate(version);
 		version_list.reverse();
 		for k in 0..version_list.len() {
 			assert_eq!(version_list[k], version_list[k]);
@@ -354,4 +1188,888 @@ mod test {
 			assert!(version_list[j] > version_list[i]);
 		}
 	}
+
+	#[test]
+	fn is_leaf() {
+		let mut versions = vec![Version::new()];
+		let mut has_child = vec![false];
+		for _ in 0..1000 {
+			let i = fastrand::usize(..versions.len());
+			let child = versions[i].insert_after();
+			assert!(!versions[i].is_leaf());
+			assert!(child.is_leaf());
+			versions.push(child);
+			has_child[i] = true;
+			has_child.push(false);
+		}
+		for (version, has_child) in versions.iter().zip(has_child) {
+			assert_eq!(version.is_leaf(), !has_child);
+		}
+		let mut chain = Version::new();
+		for _ in 0..200 {
+			let next = chain.insert_after();
+			assert!(!chain.is_leaf());
+			assert!(next.is_leaf());
+			chain = next;
+		}
+	}
+
+	#[test]
+	fn delete() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		for _ in 0..500 {
+			let i = fastrand::usize(..version_list.len());
+			unsafe { version_list.remove(i).delete() };
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		for _ in 0..500 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		while let Some(version) = version_list.pop() {
+			unsafe { version.delete() };
+			assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		}
+	}
+
+	#[test]
+	fn delete_super_nodes() {
+		let base = PartialVersion::new();
+		let mut version_list = vec![base];
+		for _ in 0..200 {
+			version_list.push(version_list.last().unwrap().insert_after());
+		}
+		// Deleting from the front empties whole super nodes, including the base super node.
+		for version in version_list.drain(..150) {
+			unsafe { version.delete() };
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		for _ in 0..200 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+	}
+
+	#[cfg(feature = "renumber-stats")]
+	#[test]
+	fn adversarial_relabels() {
+		let n = 100000;
+		let version = PartialVersion::new();
+		super::reset_renumber_count();
+		for _ in 0..n {
+			version.insert_after();
+		}
+		// Every split relabels 64 nodes and happens every 32 insertions, and renumbering the super
+		// nodes adds a little on top of that.
+		let count = super::renumber_count();
+		assert!(count >= 2 * n - 64);
+		assert!(count <= 4 * n);
+	}
+
+	#[test]
+	fn owned() {
+		for _ in 0..100 {
+			let base = PartialVersion::new();
+			let owner = unsafe { OwnedVersionList::new(base) };
+			let mut version_list = vec![base];
+			for _ in 0..100 {
+				let i = fastrand::usize(..version_list.len());
+				let new_version = version_list[i].insert_after();
+				version_list.insert(i + 1, new_version);
+			}
+			let clone = owner.clone();
+			drop(owner);
+			assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+			drop(clone);
+		}
+		let base = PartialVersion::new();
+		let owner = unsafe { OwnedVersionList::new(base) };
+		let version = base.insert_after();
+		unsafe {
+			base.delete();
+			version.delete();
+		}
+		drop(owner);
+	}
+
+	#[cfg(feature = "alloc-stats")]
+	#[test]
+	fn owned_frees_all() {
+		crate::reset_alloc_count();
+		let version = Version::new();
+		let owner = unsafe { OwnedVersionList::new(version.primary) };
+		let mut versions = vec![version];
+		for _ in 0..10000 {
+			let version = versions[fastrand::usize(..versions.len())];
+			versions.push(version.insert_after());
+		}
+		unsafe { versions.pop().unwrap().delete() };
+		drop(owner);
+		assert_eq!(crate::alloc_count(), crate::free_count());
+
+		// Versions inserted before other versions and lists with other block sizes are freed too
+		crate::reset_alloc_count();
+		let version = Version::<4>::with_block_size();
+		let owner = unsafe { OwnedVersionList::new(version.primary) };
+		let mut versions = vec![version];
+		for _ in 0..10000 {
+			let version = versions[fastrand::usize(..versions.len())];
+			versions.push(if fastrand::bool() {
+				version.insert_after()
+			} else {
+				version.insert_before()
+			});
+		}
+		assert!(crate::alloc_count() > 20000);
+		drop(owner);
+		assert_eq!(crate::alloc_count(), crate::free_count());
+	}
+
+	#[cfg(feature = "alloc-stats")]
+	#[test]
+	fn unowned_freed_by_last_delete() {
+		crate::reset_alloc_count();
+		let version = Version::<4>::with_block_size();
+		let mut versions = vec![version.primary, version.secondary];
+		for _ in 0..100 {
+			let version = versions[fastrand::usize(..versions.len())];
+			versions.push(version.insert_after());
+		}
+		fastrand::shuffle(&mut versions);
+		for version in versions {
+			assert!(crate::alloc_count() > crate::free_count());
+			unsafe { version.delete() };
+		}
+		assert_eq!(crate::alloc_count(), crate::free_count());
+	}
+
+	#[test]
+	fn hash() {
+		let mut map = std::collections::HashMap::new();
+		let base = Version::new();
+		let mut versions = vec![base];
+		map.insert(base, 0);
+		// Inserting after the same version relabels the versions many times
+		for i in 1..10000 {
+			let version = base.insert_after();
+			versions.push(version);
+			map.insert(version, i);
+		}
+		for (i, version) in versions.iter().enumerate() {
+			assert_eq!(map.get(version), Some(&i));
+		}
+		let partial: std::collections::HashSet<_> = versions.iter().map(|v| v.primary).collect();
+		assert_eq!(partial.len(), versions.len());
+		assert!(versions.iter().all(|v| partial.contains(&v.primary)));
+	}
+
+	#[test]
+	fn owned_many_lists() {
+		// Run under Miri to check that every list is freed.
+		let lists = if cfg!(miri) { 200 } else { 5000 };
+		for _ in 0..lists {
+			let version = Version::new();
+			let owner = unsafe { OwnedVersionList::new(version.primary) };
+			let mut versions = vec![version];
+			for _ in 0..fastrand::usize(..80) {
+				let version = versions[fastrand::usize(..versions.len())];
+				versions.push(version.insert_after());
+			}
+			assert!(versions
+				.iter()
+				.skip(1)
+				.all(|version| versions[0] < *version));
+			drop(owner);
+		}
+	}
+
+	#[cfg(not(feature = "trace-version"))]
+	#[test]
+	fn no_trace() {
+		let evaluated = std::cell::Cell::new(false);
+		trace!("{}", {
+			evaluated.set(true);
+			0
+		});
+		assert!(!evaluated.get());
+	}
+
+	#[test]
+	#[cfg_attr(miri, ignore = "Miri can not spawn processes")]
+	fn trace_output() {
+		// The harness captures what the test prints, so the versions are inserted in a child
+		// process of this test which prints to stderr
+		if std::env::var_os("PERSISTENCY_TRACE_CHILD").is_some() {
+			// Inserting directly after the root splits its super node, until there is no room for
+			// a new super node and the super nodes are renumbered
+			let root = PartialVersion::<4>::with_block_size();
+			for _ in 0..500 {
+				root.insert_after();
+			}
+			return;
+		}
+		let output = std::process::Command::new(std::env::current_exe().unwrap())
+			.args(["--exact", "version::test::trace_output", "--nocapture"])
+			.env("PERSISTENCY_TRACE_CHILD", "1")
+			.output()
+			.unwrap();
+		assert!(output.status.success());
+		// The child ran the insert loop rather than no test at all
+		assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+		let stderr = String::from_utf8(output.stderr).unwrap();
+		assert_eq!(
+			stderr.contains("splitting super node"),
+			cfg!(feature = "trace-version")
+		);
+		assert_eq!(
+			stderr.contains("renumbering"),
+			cfg!(feature = "trace-version")
+		);
+	}
+
+	#[test]
+	fn try_cmp() {
+		let a = Version::new();
+		let b = Version::new();
+		let a_child = a.insert_after();
+		assert_eq!(a.try_cmp(&b), None);
+		assert_eq!(a_child.try_cmp(&b), None);
+		assert_eq!(a.primary.try_cmp(&b.primary), None);
+		assert_eq!(a.try_cmp(&a_child), Some(Ordering::Less));
+		assert_eq!(a_child.try_cmp(&a), Some(Ordering::Greater));
+		assert_eq!(a.try_cmp(&a), Some(Ordering::Equal));
+		// Splitting super nodes does not change which list a version belongs to
+		let mut version = a;
+		for _ in 0..1000 {
+			version = a.insert_after();
+		}
+		assert_eq!(version.try_cmp(&a), Some(Ordering::Greater));
+		assert_eq!(version.try_cmp(&b), None);
+	}
+
+	#[test]
+	fn adversarial_million() {
+		let n = if cfg!(miri) { 1000 } else { 1000000 };
+		let base = PartialVersion::new();
+		let mut version_list = vec![base];
+		for _ in 0..n {
+			version_list.push(base.insert_after());
+		}
+		// Every version is inserted directly after the base, so the newest version is first
+		version_list[1..].reverse();
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+	}
+
+	#[test]
+	fn delete_then_insert_same_place() {
+		let base = PartialVersion::new();
+		let mut version_list = vec![base];
+		// Deleting keeps the super node from filling up, so the room after the last version runs
+		// out before the super node is split.
+		for _ in 0..3 {
+			for _ in 0..60 {
+				version_list.push(version_list.last().unwrap().insert_after());
+			}
+			assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+			let last = version_list.len() - 1;
+			for version in version_list.drain(1..last) {
+				unsafe { version.delete() };
+			}
+		}
+	}
+
+	#[test]
+	fn insert_before() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..10000 {
+			let i = fastrand::usize(..version_list.len());
+			if fastrand::bool() {
+				let new_version = version_list[i].insert_after();
+				version_list.insert(i + 1, new_version);
+			} else {
+				let new_version = version_list[i].insert_before();
+				version_list.insert(i, new_version);
+			}
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		let first = version_list[0];
+		let mut version_list = vec![first];
+		for _ in 0..1000 {
+			version_list.insert(0, version_list[0].insert_before());
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+	}
+
+	#[test]
+	fn version_insert_before() {
+		let root = Version::new();
+		let child = root.insert_after();
+		let before = child.insert_before();
+		assert!(root < before);
+		assert!(before < child);
+		assert!(before.secondary < child.primary);
+		assert!(child.secondary < root.secondary);
+		assert!(!root.is_leaf());
+		assert!(before.is_leaf());
+		assert!(child.is_leaf());
+	}
+
+	#[test]
+	fn rank() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		let mut shuffled = version_list.clone();
+		fastrand::shuffle(&mut shuffled);
+		shuffled.sort_by_key(|version| version.rank());
+		assert_eq!(shuffled, version_list);
+		assert!(version_list.windows(2).all(|w| w[0].rank() < w[1].rank()));
+	}
+
+	#[test]
+	fn iter_versions() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..50 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		let iterated: Vec<_> = super::iter_versions(version_list[17]).collect();
+		assert_eq!(iterated, version_list);
+		assert!(iterated
+			.windows(2)
+			.all(|w| w[0].cmp(&w[1]) == Ordering::Less));
+
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..1000 {
+			let new_version = version_list.last().unwrap().insert_after();
+			version_list.push(new_version);
+		}
+		assert_eq!(super::iter_versions(version_list[0]).count(), 1001);
+		assert!(super::iter_versions(version_list[0]).eq(version_list));
+	}
+
+	#[test]
+	fn count_between() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..99 {
+			let new_version = version_list.last().unwrap().insert_after();
+			version_list.push(new_version);
+		}
+		let (first, last) = (version_list[0], version_list[99]);
+		assert_eq!(super::count_between(first, last), Some(98));
+		assert_eq!(super::count_between(last, first), None);
+		assert_eq!(super::count_between(first, first), Some(0));
+		assert_eq!(super::count_between(first, version_list[1]), Some(0));
+		assert_eq!(super::count_between(first, PartialVersion::new()), None);
+
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		for _ in 0..100 {
+			let i = fastrand::usize(..version_list.len());
+			let j = fastrand::usize(i..version_list.len());
+			let expected = (j - i).saturating_sub(1);
+			assert_eq!(
+				super::count_between(version_list[i], version_list[j]),
+				Some(expected)
+			);
+		}
+	}
+
+	fn random_ordering<const B: usize>() {
+		let mut version_list = vec![PartialVersion::<B>::with_block_size()];
+		for _ in 0..10000 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = if fastrand::bool() {
+				version_list[i].insert_after()
+			} else {
+				version_list[i].insert_before()
+			};
+			let index = if new_version > version_list[i] {
+				i + 1
+			} else {
+				i
+			};
+			version_list.insert(index, new_version);
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		assert!(super::iter_versions(version_list[0]).eq(version_list.iter().copied()));
+		for _ in 0..version_list.len() {
+			let i = fastrand::usize(..version_list.len() - 1);
+			let j = fastrand::usize(i + 1..version_list.len());
+			assert!(version_list[i] < version_list[j]);
+			assert!(version_list[j] > version_list[i]);
+		}
+	}
+
+	#[test]
+	fn block_size() {
+		random_ordering::<16>();
+		random_ordering::<2>();
+		random_ordering::<3>();
+		random_ordering::<64>();
+		let root = Version::<16>::with_block_size();
+		let mut versions = vec![root];
+		for _ in 0..100 {
+			versions.push(versions.last().unwrap().insert_after());
+		}
+		assert!(versions.windows(2).all(|w| w[0] < w[1]));
+		assert!(versions[1..]
+			.iter()
+			.all(|version| version.secondary < root.secondary));
+	}
+
+	#[test]
+	fn iter_from_start() {
+		let first = PartialVersion::new();
+		let mut versions = vec![first];
+		for _ in 0..500 {
+			let version = versions[fastrand::usize(..versions.len())];
+			versions.push(if fastrand::bool() {
+				version.insert_after()
+			} else {
+				version.insert_before()
+			});
+		}
+		let last = *versions.last().unwrap();
+		versions.sort();
+		assert!(last.iter_from_start().eq(versions.iter().copied()));
+		assert_eq!(PartialVersion::new().iter_from_start().count(), 1);
+	}
+
+	#[test]
+	fn order_key() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = version_list[i].insert_after();
+			version_list.insert(i + 1, new_version);
+		}
+		let keys: Vec<_> = version_list.iter().map(|v| v.order_key_u128()).collect();
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len());
+			let j = fastrand::usize(..version_list.len());
+			assert_eq!(keys[i].cmp(&keys[j]), version_list[i].cmp(&version_list[j]));
+			assert_eq!(
+				version_list[i]
+					.order_key()
+					.cmp(&version_list[j].order_key()),
+				version_list[i].cmp(&version_list[j])
+			);
+		}
+		let (major, minor) = version_list[500].order_key();
+		assert_eq!(keys[500], ((major as u128) << 64) | minor as u128);
+	}
+
+	#[test]
+	fn ancestors() {
+		// Model tree as the parent index of every version
+		let mut versions = vec![Version::<8>::with_block_size()];
+		let mut parents = vec![None];
+		for _ in 0..500 {
+			let i = fastrand::usize(..versions.len());
+			if fastrand::u8(..4) == 0 {
+				versions.push(versions[i].insert_before());
+				parents.push(parents[i]);
+			} else {
+				versions.push(versions[i].insert_after());
+				parents.push(Some(i));
+			}
+		}
+		let ancestors = |mut i: usize| {
+			let mut ancestors = vec![i];
+			while let Some(parent) = parents[i] {
+				ancestors.push(parent);
+				i = parent;
+			}
+			ancestors
+		};
+		for i in 0..versions.len() {
+			assert!(versions[i].parent() == parents[i].map(|parent| versions[parent]));
+			let ancestors_i = ancestors(i);
+			for j in 0..versions.len() {
+				let ancestors_j = ancestors(j);
+				assert_eq!(
+					versions[i].is_ancestor_of(versions[j]),
+					ancestors_j.contains(&i)
+				);
+				let lca = ancestors_i
+					.iter()
+					.find(|ancestor| ancestors_j.contains(ancestor))
+					.map(|&ancestor| versions[ancestor]);
+				assert!(Version::lca(versions[i], versions[j]) == lca);
+			}
+		}
+
+		// Siblings of the root and versions of other lists have no common ancestor
+		let root = versions[0];
+		let sibling = root.insert_before();
+		assert!(sibling.parent().is_none());
+		assert!(Version::lca(root.insert_after(), sibling.insert_after()).is_none());
+		let other = Version::<8>::with_block_size();
+		assert!(!other.is_ancestor_of(root) && !root.is_ancestor_of(other));
+		assert!(Version::lca(root, other).is_none());
+	}
+
+	#[test]
+	fn version_count() {
+		let base = PartialVersion::new();
+		let owner = unsafe { OwnedVersionList::new(base) };
+		assert_eq!(owner.version_count(), 1);
+		let mut version_list = vec![base];
+		for n in 1..=1000 {
+			let i = fastrand::usize(..version_list.len());
+			version_list.push(version_list[i].insert_after());
+			assert_eq!(owner.version_count(), n + 1);
+		}
+		unsafe { version_list.pop().unwrap().delete() };
+		assert_eq!(owner.clone().version_count(), 1000);
+	}
+
+	#[test]
+	fn list_id() {
+		let root = Version::new();
+		let a = root.insert_after();
+		let b = root.insert_after();
+		let c = a.insert_after();
+		assert_eq!(root.list_id(), c.list_id());
+		assert_eq!(a.list_id(), b.list_id());
+		assert_eq!(c.primary.list_id(), c.secondary.list_id());
+
+		let other = Version::new();
+		assert_ne!(root.list_id(), other.list_id());
+		assert_eq!(other.list_id(), other.insert_after().list_id());
+		let ids: HashSet<_> = [root, a, b, c, other].map(Version::list_id).into();
+		assert_eq!(ids.len(), 2);
+	}
+
+	#[cfg(debug_assertions)]
+	#[test]
+	#[should_panic = "compared versions from different version lists"]
+	fn cross_list_cmp() {
+		let a = PartialVersion::new();
+		let b = PartialVersion::new();
+		let _ = a < b;
+	}
+
+	#[test]
+	fn cross_list_eq() {
+		let a = Version::new();
+		let b = Version::new();
+		assert!(a != b && a.primary != b.primary);
+		assert!(a == a && a.secondary == a.secondary);
+		// Hash sets probe versions from other lists with `==`
+		let set: HashSet<_> = [a.primary, a.insert_after().primary].into();
+		assert!(!set.contains(&b.primary));
+		assert!(set.contains(&a.primary));
+	}
+
+	#[test]
+	fn insert_before_only() {
+		let first = PartialVersion::new();
+		let mut created = vec![first];
+		for _ in 0..1000 {
+			created.push(created.last().unwrap().insert_before());
+		}
+		// Every version is created before the previous one, so the order is the reverse of the
+		// creation order.
+		assert!(created.windows(2).all(|w| w[0] > w[1]));
+		created.reverse();
+		assert!(first.iter_from_start().eq(created.iter().copied()));
+
+		// Inserting before the same version repeatedly puts the new versions in creation order
+		let last = PartialVersion::new();
+		let mut created = vec![];
+		for _ in 0..1000 {
+			created.push(last.insert_before());
+		}
+		created.push(last);
+		assert!(created.windows(2).all(|w| w[0] < w[1]));
+	}
+
+	fn interleaved_delete<const B: usize>() {
+		let base = PartialVersion::<B>::with_block_size();
+		let owner = unsafe { OwnedVersionList::new(base) };
+		let mut version_list = vec![base];
+		for _ in 0..10000 {
+			let i = fastrand::usize(..version_list.len());
+			match fastrand::u8(..3) {
+				0 if version_list.len() > 1 => unsafe { version_list.remove(i).delete() },
+				0 | 1 => {
+					let new_version = version_list[i].insert_after();
+					version_list.insert(i + 1, new_version);
+				}
+				_ => {
+					let new_version = version_list[i].insert_before();
+					version_list.insert(i, new_version);
+				}
+			}
+		}
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		assert!(version_list[0]
+			.iter_from_start()
+			.eq(version_list.iter().copied()));
+		assert_eq!(owner.version_count(), version_list.len());
+	}
+
+	#[test]
+	fn interleaved_insert_delete() {
+		interleaved_delete::<64>();
+		interleaved_delete::<4>();
+	}
+
+	/// Checks the internal consistency of the version list of the version. Panics if the super
+	/// nodes are not a ring containing the base and the version, if the sizes of the super nodes
+	/// and the list do not match the number of nodes, or if the labels are not strictly
+	/// increasing from the base.
+	fn validate<const B: usize>(version: PartialVersion<B>) {
+		unsafe {
+			let list = version.list();
+			let base = super::list_base(list);
+			let mut super_node = base;
+			let mut prev_value = None;
+			let mut size = 0;
+			let mut found = false;
+			loop {
+				assert!(super::super_node_parent(super_node) == list);
+				let value =
+					super::super_node_value(super_node).wrapping_sub(super::super_node_value(base));
+				assert!(
+					prev_value < Some(value),
+					"super node labels are not increasing"
+				);
+				prev_value = Some(value);
+
+				let mut node = Some(super::super_node_list(super_node));
+				let mut node_value = None;
+				let mut count = 0;
+				while let Some(current) = node {
+					assert!(super::node_parent(current) == super_node);
+					let value = super::node_value(current);
+					assert!(node_value < Some(value), "node labels are not increasing");
+					node_value = Some(value);
+					found |= current == version.node;
+					count += 1;
+					node = super::node_next(current);
+				}
+				assert_eq!(super::super_node_size(super_node), count);
+				assert!(0 < count && count < B, "super node has {} nodes", count);
+				size += count;
+
+				super_node = super::super_node_next(super_node);
+				if super_node == base {
+					break;
+				}
+			}
+			assert_eq!(list.as_ref().size, size);
+			assert!(found, "the version is not reachable from the base");
+		}
+	}
+
+	/// The number of super nodes of the version list of the version.
+	fn super_node_count<const B: usize>(version: PartialVersion<B>) -> usize {
+		unsafe {
+			let base = super::list_base(version.list());
+			let mut count = 1;
+			let mut super_node = super::super_node_next(base);
+			while super_node != base {
+				count += 1;
+				super_node = super::super_node_next(super_node);
+			}
+			count
+		}
+	}
+
+	#[test]
+	fn delete_merges_super_nodes() {
+		let base = PartialVersion::new();
+		let mut version_list = vec![base];
+		for _ in 0..999 {
+			version_list.push(version_list.last().unwrap().insert_after());
+		}
+		let mut survivors = vec![];
+		for (i, version) in version_list.into_iter().enumerate() {
+			if i % 2 == 0 {
+				survivors.push(version);
+			} else {
+				unsafe { version.delete() };
+			}
+		}
+		assert!(survivors.windows(2).all(|w| w[0] < w[1]));
+		for _ in 0..1000 {
+			let i = fastrand::usize(..survivors.len());
+			let j = fastrand::usize(..survivors.len());
+			assert_eq!(survivors[i].cmp(&survivors[j]), i.cmp(&j));
+		}
+
+		// Deleting three of every four versions leaves super nodes small enough to be merged
+		let super_nodes = super_node_count(base);
+		let mut remaining = vec![];
+		for (i, version) in survivors.into_iter().enumerate() {
+			if i % 2 == 0 {
+				remaining.push(version);
+			} else {
+				unsafe { version.delete() };
+			}
+		}
+		assert!(super_node_count(base) < super_nodes);
+		assert!(remaining.windows(2).all(|w| w[0] < w[1]));
+		for _ in 0..1000 {
+			let i = fastrand::usize(..remaining.len());
+			let new_version = remaining[i].insert_after();
+			remaining.insert(i + 1, new_version);
+		}
+		assert!(remaining.windows(2).all(|w| w[0] < w[1]));
+		assert!(base.iter_from_start().eq(remaining.iter().copied()));
+	}
+
+	#[test]
+	fn successor_predecessor() {
+		let mut version_list = vec![PartialVersion::new()];
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len());
+			let new_version = if fastrand::bool() {
+				version_list[i].insert_after()
+			} else {
+				version_list[i].insert_before()
+			};
+			let index = if new_version > version_list[i] {
+				i + 1
+			} else {
+				i
+			};
+			version_list.insert(index, new_version);
+		}
+		for (i, &version) in version_list.iter().enumerate() {
+			assert_eq!(version.successor(), version_list.get(i + 1).copied());
+			assert_eq!(
+				version.predecessor(),
+				i.checked_sub(1).map(|i| version_list[i])
+			);
+			if let Some(successor) = version.successor() {
+				assert_eq!(successor.predecessor(), Some(version));
+			}
+		}
+		let first = version_list[0];
+		assert!(first.predecessor().is_none());
+		assert!(version_list.last().unwrap().successor().is_none());
+	}
+
+	#[test]
+	fn adversarial_insert_before() {
+		let n = if cfg!(miri) { 1000 } else { 100000 };
+		let mut smallest = PartialVersion::new();
+		let mut version_list = vec![smallest];
+		for _ in 0..n {
+			smallest = smallest.insert_before();
+			version_list.push(smallest);
+		}
+		version_list.reverse();
+		assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+		for _ in 0..1000 {
+			let i = fastrand::usize(..version_list.len() - 1);
+			let j = fastrand::usize(i + 1..version_list.len());
+			assert!(version_list[i] < version_list[j]);
+		}
+	}
+
+	#[test]
+	fn insert_before_super_node_boundaries() {
+		// Sequential inserts fill super nodes, so some of the versions are the first version of a
+		// super node which is not the base super node.
+		let mut version_list = vec![Version::new()];
+		for _ in 0..300 {
+			version_list.push(version_list.last().unwrap().insert_after());
+		}
+		let mut before: Vec<_> = version_list
+			.iter()
+			.map(|version| version.insert_before())
+			.collect();
+		for (new, old) in before.iter().zip(&version_list) {
+			assert!(new.secondary < old.primary);
+			assert!(new.primary.successor() == Some(new.secondary));
+			assert!(new.secondary.successor() == Some(old.primary));
+		}
+		let mut all: Vec<_> = version_list
+			.iter()
+			.flat_map(|v| [v.primary, v.secondary])
+			.collect();
+		all.extend(before.iter().flat_map(|v| [v.primary, v.secondary]));
+		all.sort();
+		assert!(all[0].iter_from_start().eq(all.iter().copied()));
+		before.sort();
+		assert!(before.windows(2).all(|w| w[0] < w[1]));
+	}
+
+	#[test]
+	fn arena() {
+		let mut arena = VersionArena::<16>::with_block_size();
+		for _ in 0..2 {
+			for _ in 0..20 {
+				let mut version_list = vec![unsafe { PartialVersion::new_in(&arena) }];
+				for _ in 0..200 {
+					let i = fastrand::usize(..version_list.len());
+					let new_version = version_list[i].insert_after();
+					version_list.insert(i + 1, new_version);
+				}
+				for _ in 0..100 {
+					let i = fastrand::usize(..version_list.len());
+					unsafe { version_list.remove(i).delete() };
+				}
+				assert!(version_list.windows(2).all(|w| w[0] < w[1]));
+				assert!(version_list[0]
+					.iter_from_start()
+					.eq(version_list.iter().copied()));
+				let owner = unsafe { OwnedVersionList::new(version_list[0]) };
+				assert_eq!(owner.version_count(), 101);
+			}
+			unsafe { arena.reset() };
+		}
+		let arena = VersionArena::new();
+		let root = unsafe { Version::new_in(&arena) };
+		let child = root.insert_after();
+		assert!(root < child);
+		assert!(child.secondary < root.secondary);
+	}
+
+	#[cfg(feature = "alloc-stats")]
+	#[test]
+	fn arena_allocations() {
+		fn build(mut new: impl FnMut() -> PartialVersion) {
+			for _ in 0..1000 {
+				let mut version = new();
+				for _ in 0..10 {
+					version = version.insert_after();
+				}
+			}
+		}
+		crate::reset_alloc_count();
+		build(PartialVersion::new);
+		let without_arena = crate::alloc_count();
+		assert_eq!(without_arena, 1000 * 13);
+
+		crate::reset_alloc_count();
+		let arena = VersionArena::new();
+		build(|| unsafe { PartialVersion::new_in(&arena) });
+		let with_arena = crate::alloc_count();
+		assert!(with_arena * 100 < without_arena);
+		drop(arena);
+		assert_eq!(crate::alloc_count(), crate::free_count());
+	}
 }